@@ -12,6 +12,7 @@ struct Args {
 	closureslop_crate: Option<Path>,
 	id: Option<LitStr>,
 	event: Type,
+	monitor: bool,
 	order: OrderInfo,
 }
 
@@ -31,12 +32,22 @@ impl Parse for Args {
 
 		let mut id = None;
 		let mut event = None;
+		let mut monitor = false;
 		let mut before = Vec::new();
 		let mut after = Vec::new();
 		while let Ok(keyword) = input.parse::<Ident>() {
-			input.parse::<Token![:]>()?;
+			// monitor is the only keyword without a value
+			if keyword != "monitor" {
+				input.parse::<Token![:]>()?;
+			}
 
 			match keyword.to_string().as_str() {
+				"monitor" => {
+					if monitor {
+						return Err(Error::new(keyword.span(), "unexpected monitor"));
+					}
+					monitor = true;
+				}
 				"group" => {
 					if id.is_some() {
 						return Err(Error::new(keyword.span(), "unexpected group"));
@@ -74,6 +85,7 @@ impl Parse for Args {
 			closureslop_crate,
 			id,
 			event,
+			monitor,
 			order: OrderInfo { before, after },
 		})
 	}
@@ -84,6 +96,7 @@ pub fn callback(args: TokenStream, input: TokenStream) -> TokenStream {
 		closureslop_crate,
 		id,
 		event,
+		monitor,
 		order: OrderInfo { before, after },
 	} = parse_macro_input!(args as Args);
 	let input = parse_macro_input!(input as ItemFn);
@@ -103,16 +116,29 @@ pub fn callback(args: TokenStream, input: TokenStream) -> TokenStream {
 	// we need it to write out the type of the reactor
 	let context_path = get_context_type(&input);
 
+	let add_callback = match monitor {
+		false => quote! {
+			#closureslop_path::add_callback!(reactor, #event => #callback_name => |ctx, args| {
+				#closureslop_path::__private_macroslop::smallbox::SmallBox::new(async move {
+					#function_name(ctx, args).await
+				})
+			}, #(before: #before,)* #(after: #after,)*);
+		},
+		true => quote! {
+			#closureslop_path::add_monitor!(reactor, #event => #callback_name => |ctx, args, result| {
+				#closureslop_path::__private_macroslop::smallbox::SmallBox::new(async move {
+					#function_name(ctx, args, result).await
+				})
+			}, #(before: #before,)* #(after: #after,)*);
+		},
+	};
+
 	quote! {
 		const _: () = {
 			#[#closureslop_path::__private_macroslop::linkme::distributed_slice(crate::#collector_name)]
 			#[linkme(crate = #closureslop_path::__private_macroslop::linkme)]
 			fn _add_callback(reactor: &mut #closureslop_path::Reactor<#context_path>) {
-				#closureslop_path::add_callback!(reactor, #event => #callback_name => |ctx, args| {
-					#closureslop_path::__private_macroslop::smallbox::SmallBox::new(async move {
-						#function_name(ctx, args).await
-					})
-				}, #(before: #before,)* #(after: #after,)*);
+				#add_callback
 			}
 		};
 
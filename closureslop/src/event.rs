@@ -15,3 +15,13 @@ pub trait Event: Any {
 	/// The type of the return value of the event
 	type Return;
 }
+
+/// Declares that an event is a member of the event group `G` (a parent category), so that the
+/// callbacks of `G` can handle all of its members at once.
+///
+/// Use [`Reactor::trigger_with_group`][crate::Reactor::trigger_with_group] to trigger the event together
+/// with its group. Groups can be members of other groups too.
+pub trait Member<G: Event>: Event {
+	/// Builds the arguments of the group event from a read-only view of the arguments of this event
+	fn group_args<'a>(args: &'a Self::Args<'_>) -> G::Args<'a>;
+}
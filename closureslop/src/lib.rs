@@ -95,6 +95,9 @@ mod tests;
 /// - `group` - **optional** identifier for the group of callbacks to add it to. Must have a respective [`init!`][crate::init].
 /// - `before` - **optional** callback id, that this callback must be executed before at runtime.
 /// - `after` - **optional** callback id, that this callback must be executed after at runtime.
+/// - `monitor` - **optional** flag without a value, makes this a monitor (see [`add_monitor!`][crate::add_monitor]).
+///   Monitors take the arguments by shared reference and the result of the event as a third argument:
+///   `async fn(ctx: &CTX, args: &Args, result: &ControlFlow<Return>)`.
 ///
 /// For `before` and `after` arguments, the callback IDs are in the format of `"defining_crate_name:function_name"` if
 /// registered using this attribute macro. The [`add_callback!`][crate::add_callback] macro allows to set a custom name.
@@ -165,8 +168,8 @@ pub use closureslop_macros::init;
 /// ```
 pub use closureslop_macros::reg;
 
-pub use event::Event;
-pub use reactor::Reactor;
+pub use event::{Event, Member};
+pub use reactor::{CallbackHandle, CallbackStats, PanicPolicy, Reactor};
 
 /// The stack size of the smallboxes of futures in async closures.
 #[doc(hidden)]
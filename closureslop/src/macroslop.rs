@@ -39,14 +39,80 @@
 #[macro_export]
 macro_rules! add_callback {
 	($reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {
-		$crate::__internal_add_callback!($reactor, $event => $name => $callback $(, $($order)* )?);
+		$crate::__internal_add_callback!(add_callback: $reactor, $event => $name => $callback $(, $($order)* )?);
+	};
+}
+
+/// Registers a callback to a reactor instance that is already in use, returning a
+/// [`CallbackHandle`][crate::CallbackHandle] that removes the callback when dropped.
+///
+/// The syntax is the same as of [`add_callback!`][crate::add_callback], but the reactor is only borrowed immutably.
+///
+/// # Example
+///
+/// ```
+/// # use closureslop::{add_runtime_callback, Event, Reactor};
+/// # use std::ops::ControlFlow;
+/// # use smallbox::SmallBox;
+/// # struct MyEvent;
+/// # impl Event for MyEvent { type Args<'a> = &'a str; type Return = (); }
+/// # let reactor: Reactor<()> = Reactor::new();
+/// let handle = add_runtime_callback!(reactor, MyEvent => "my_callback" => |ctx: &(), args: &mut &str| SmallBox::new(async move {
+///		// your code here
+///		ControlFlow::Continue(())
+/// }), before: "another_crate:another_callback");
+///
+/// // the callback is removed
+/// drop(handle);
+/// ```
+#[macro_export]
+macro_rules! add_runtime_callback {
+	($reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {
+		$crate::__internal_add_callback!(add_runtime_callback: $reactor, $event => $name => $callback $(, $($order)* )?)
+	};
+}
+
+/// Registers a monitor to a reactor instance.
+///
+/// Monitors run after all normal callbacks of the event, even if it was stopped. They get the arguments
+/// read-only and the final result of the event, which they can't change. The syntax is the same as of
+/// [`add_callback!`][crate::add_callback], and ordering requests are relative to other monitors.
+///
+/// # Example
+///
+/// ```
+/// # use closureslop::{add_monitor, Event, Reactor};
+/// # use std::ops::ControlFlow;
+/// # use smallbox::SmallBox;
+/// # struct MyEvent;
+/// # impl Event for MyEvent { type Args<'a> = &'a str; type Return = (); }
+/// # let mut reactor: Reactor<()> = Reactor::new();
+/// add_monitor!(reactor, MyEvent => "my_monitor" => |ctx: &(), args: &&str, result: &ControlFlow<()>| SmallBox::new(async move {
+///		println!("{args} -> {result:?}");
+/// }));
+/// ```
+#[macro_export]
+macro_rules! add_monitor {
+	($reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {
+		$crate::__internal_add_callback!(add_monitor: $reactor, $event => $name => $callback $(, $($order)* )?);
+	};
+}
+
+/// Registers a monitor to a reactor instance that is already in use, returning a
+/// [`CallbackHandle`][crate::CallbackHandle] that removes the monitor when dropped.
+///
+/// See [`add_monitor!`][crate::add_monitor] and [`add_runtime_callback!`][crate::add_runtime_callback].
+#[macro_export]
+macro_rules! add_runtime_monitor {
+	($reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {
+		$crate::__internal_add_callback!(add_runtime_monitor: $reactor, $event => $name => $callback $(, $($order)* )?)
 	};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __internal_add_callback {
-	($reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {
+	($method:ident: $reactor:expr, $event:ty => $name:expr => $callback:expr $(, $($order:tt)* )?) => {{
 		#[allow(unused_mut)]
 		let mut after = Vec::new();
 		#[allow(unused_mut)]
@@ -56,13 +122,13 @@ macro_rules! __internal_add_callback {
 			$crate::__internal_add_callback!(@order: after, before => [ $($order)* ]);
 		)?
 
-		$reactor.add_callback::<$event, _>(
+		$reactor.$method::<$event, _>(
 			format!("{}:{}", env!("CARGO_CRATE_NAME"), $name),
 			after,
 			before,
 			$callback,
-		);
-	};
+		)
+	}};
 	(@order: $after:ident, $before:ident => []) => {};
 	(@order: $after:ident, $before:ident => [after: $target:expr $(, $($order:tt)* )?]) => {
 		$after.push($target.to_string());
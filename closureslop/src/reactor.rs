@@ -1,32 +1,112 @@
 mod callbacks;
+mod panics;
+mod profiling;
 
-use crate::{_SmallBoxSize, Event};
+use crate::{_SmallBoxSize, Event, Member};
 use callbacks::{Callback, Callbacks};
+use panics::{catch_unwind, panic_message};
+use profiling::Profiler;
 use smallbox::SmallBox;
 use std::{
 	any::{Any, TypeId},
 	collections::BTreeMap,
 	marker::PhantomData,
 	ops::ControlFlow,
+	sync::{
+		Arc, RwLock, Weak,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::Duration,
 };
+use tracing::{error, warn};
+
+pub use panics::PanicPolicy;
+pub use profiling::CallbackStats;
+
+// The callbacks of each event are replaced as a whole when changed, so triggers that are already
+// running keep using the old ones and never have to wait
+#[derive(Default)]
+struct Events {
+	callbacks: BTreeMap<TypeId, Arc<Callbacks>>,
+	monitors: BTreeMap<TypeId, Arc<Callbacks>>,
+	settings: Settings,
+}
+
+#[derive(Default, Clone)]
+struct Settings {
+	// Some if profiling is enabled
+	profiler: Option<Arc<Profiler>>,
+	panic_policy: PanicPolicy,
+}
+
+// The output of a callback or a monitor
+trait Outcome {
+	fn is_break(&self) -> bool;
+}
+
+// The real types of the type erased closures
+type DynCallback<CTX, E> = Box<
+	dyn for<'a> Fn(
+			&'a CTX,
+			&'a mut <E as Event>::Args<'_>,
+		) -> SmallBox<
+			dyn Future<Output = ControlFlow<<E as Event>::Return>> + Send + 'a,
+			_SmallBoxSize,
+		> + Send
+		+ Sync
+		+ 'static,
+>;
+type DynMonitor<CTX, E> = Box<
+	dyn for<'a> Fn(
+			&'a CTX,
+			&'a <E as Event>::Args<'_>,
+			&'a ControlFlow<<E as Event>::Return>,
+		) -> SmallBox<dyn Future<Output = ()> + Send + 'a, _SmallBoxSize>
+		+ Send
+		+ Sync
+		+ 'static,
+>;
 
 /// The reactor structure allows to register functions that will run on specific events
 /// and then trigger the events
 ///
 /// The reactor is generic over the context type `CTX`, which is the type of the context that will be
 /// passed to the event handlers
+///
+/// There are two kinds of callbacks: normal callbacks run in order and can modify the arguments
+/// and stop the event, while monitors run after them, even if the event was stopped, and can only
+/// observe the arguments and the result.
+///
+/// Optionally, the reactor can profile the callbacks, see [`Reactor::enable_profiling`], and catch
+/// their panics, see [`Reactor::set_panic_policy`].
 pub struct Reactor<CTX> {
 	// The `dyn Any` is actually a type erased `Box<dyn Fn(...) -> ...`
 	// But we can't store it directly because Event is different for each event type
-	events: BTreeMap<TypeId, Callbacks>,
+	events: Arc<RwLock<Events>>,
 	_phantom: PhantomData<fn(CTX)>,
 }
 
+/// A callback that was added with [`Reactor::add_runtime_callback`] or [`Reactor::add_runtime_monitor`].
+///
+/// The callback is removed from the reactor when this handle is dropped.
+#[must_use = "the callback is removed when the handle is dropped"]
+pub struct CallbackHandle {
+	events: Weak<RwLock<Events>>,
+	event: TypeId,
+	monitor: bool,
+	id: String,
+	// so that a different callback with the same id, added after this one was removed, is never removed
+	token: u64,
+}
+
+// unique for every added callback
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
 impl<CTX: 'static> Reactor<CTX> {
 	/// Create a new empty reactor
 	pub fn new() -> Self {
 		Self {
-			events: BTreeMap::new(),
+			events: Arc::new(RwLock::new(Events::default())),
 			_phantom: PhantomData,
 		}
 	}
@@ -53,30 +133,216 @@ impl<CTX: 'static> Reactor<CTX> {
 		must_come_before: Vec<String>,
 		handler: F,
 	) {
-		let closure = Box::new(handler)
-			as Box<
-				dyn for<'a> Fn(
-						&'a CTX,
-						&'a mut E::Args<'_>,
-					) -> SmallBox<
-						dyn Future<Output = ControlFlow<E::Return>> + Send + 'a,
-						_SmallBoxSize,
-					> + Send
-					+ Sync
-					+ 'static,
-			>;
-		let type_erased = Box::new(closure) as Box<dyn Any + Send + Sync + 'static>;
-
-		let callbacks = self
-			.events
+		let closure = Box::new(handler) as DynCallback<CTX, E>;
+		self.insert::<E>(
+			false,
+			id,
+			must_come_after,
+			must_come_before,
+			Arc::new(closure),
+		);
+	}
+	/// Register a callback for an event while the reactor is already in use. Prefer using
+	/// the [`add_runtime_callback!`][crate::add_runtime_callback] macro instead.
+	///
+	/// The callback is removed when the returned handle is dropped. Triggers that are already running
+	/// are not affected by adding or removing callbacks.
+	///
+	/// Panics if there are cyclic dependencies detected in the callbacks (e.g. A must come after B, but B must come after A)
+	///
+	/// Panics if there's already a callback with the same id for this event.
+	pub fn add_runtime_callback<
+		E: Event,
+		F: for<'a> Fn(
+				&'a CTX,
+				&'a mut E::Args<'_>,
+			) -> SmallBox<
+				dyn Future<Output = ControlFlow<E::Return>> + Send + 'a,
+				_SmallBoxSize,
+			> + Send
+			+ Sync
+			+ 'static,
+	>(
+		&self,
+		id: String,
+		must_come_after: Vec<String>,
+		must_come_before: Vec<String>,
+		handler: F,
+	) -> CallbackHandle {
+		let closure = Box::new(handler) as DynCallback<CTX, E>;
+		let token = self.insert::<E>(
+			false,
+			id.clone(),
+			must_come_after,
+			must_come_before,
+			Arc::new(closure),
+		);
+
+		self.handle::<E>(false, id, token)
+	}
+	/// Register a monitor for an event. Prefer using macros instead.
+	///
+	/// Monitors run after all normal callbacks, even if the event was stopped, and get the final result.
+	/// They are ordered only relative to other monitors.
+	///
+	/// Panics if there are cyclic dependencies detected in the monitors (e.g. A must come after B, but B must come after A)
+	///
+	/// Panics if there's already a monitor with the same id for this event.
+	pub fn add_monitor<
+		E: Event,
+		F: for<'a> Fn(
+				&'a CTX,
+				&'a E::Args<'_>,
+				&'a ControlFlow<E::Return>,
+			) -> SmallBox<dyn Future<Output = ()> + Send + 'a, _SmallBoxSize>
+			+ Send
+			+ Sync
+			+ 'static,
+	>(
+		&mut self,
+		id: String,
+		must_come_after: Vec<String>,
+		must_come_before: Vec<String>,
+		handler: F,
+	) {
+		let closure = Box::new(handler) as DynMonitor<CTX, E>;
+		self.insert::<E>(
+			true,
+			id,
+			must_come_after,
+			must_come_before,
+			Arc::new(closure),
+		);
+	}
+	/// Register a monitor for an event while the reactor is already in use. Prefer using
+	/// the [`add_runtime_monitor!`][crate::add_runtime_monitor] macro instead.
+	///
+	/// The monitor is removed when the returned handle is dropped. See [`Reactor::add_monitor`]
+	/// and [`Reactor::add_runtime_callback`].
+	pub fn add_runtime_monitor<
+		E: Event,
+		F: for<'a> Fn(
+				&'a CTX,
+				&'a E::Args<'_>,
+				&'a ControlFlow<E::Return>,
+			) -> SmallBox<dyn Future<Output = ()> + Send + 'a, _SmallBoxSize>
+			+ Send
+			+ Sync
+			+ 'static,
+	>(
+		&self,
+		id: String,
+		must_come_after: Vec<String>,
+		must_come_before: Vec<String>,
+		handler: F,
+	) -> CallbackHandle {
+		let closure = Box::new(handler) as DynMonitor<CTX, E>;
+		let token = self.insert::<E>(
+			true,
+			id.clone(),
+			must_come_after,
+			must_come_before,
+			Arc::new(closure),
+		);
+
+		self.handle::<E>(true, id, token)
+	}
+	fn insert<E: Event>(
+		&self,
+		monitor: bool,
+		id: String,
+		must_come_after: Vec<String>,
+		must_come_before: Vec<String>,
+		callback: Arc<dyn Any + Send + Sync + 'static>,
+	) -> u64 {
+		let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+		let mut events = self.events.write().unwrap();
+		let map = match monitor {
+			false => &mut events.callbacks,
+			true => &mut events.monitors,
+		};
+		let callbacks = map
 			.entry(TypeId::of::<E>())
-			.or_insert(Callbacks::new(std::any::type_name::<E>()));
-		callbacks.add_callback(Callback {
+			.or_insert_with(|| Arc::new(Callbacks::new(std::any::type_name::<E>())));
+		// only copies the callbacks if a trigger is using them right now
+		let result = Arc::make_mut(callbacks).add_callback(Callback {
 			id,
-			callback: type_erased,
+			callback,
 			must_come_after,
 			must_come_before,
+			panics: Default::default(),
+			token,
 		});
+		// don't poison the lock
+		drop(events);
+
+		if let Err(e) = result {
+			panic!("{e}");
+		}
+
+		token
+	}
+	fn handle<E: Event>(&self, monitor: bool, id: String, token: u64) -> CallbackHandle {
+		CallbackHandle {
+			events: Arc::downgrade(&self.events),
+			event: TypeId::of::<E>(),
+			monitor,
+			id,
+			token,
+		}
+	}
+	// Runs the future of a callback, profiling it and catching panics if enabled.
+	// Returns None if it panicked and the panic was caught
+	async fn run<E: Event, F: Future<Output: Outcome>>(
+		&self,
+		settings: &Settings,
+		monitor: bool,
+		callbacks: &Callbacks,
+		callback: &Callback,
+		future: F,
+	) -> Option<F::Output> {
+		let future = async {
+			match &settings.profiler {
+				None => future.await,
+				Some(profiler) => {
+					profiler
+						.measure(monitor, callbacks.event_name, &callback.id, future)
+						.await
+				}
+			}
+		};
+
+		if settings.panic_policy == PanicPolicy::Propagate {
+			return Some(future.await);
+		}
+
+		match catch_unwind(future).await {
+			Ok(output) => Some(output),
+			Err(payload) => {
+				let panics = callback.panics.fetch_add(1, Ordering::Relaxed) + 1;
+				error!(
+					"callback {} of {} panicked: {}",
+					callback.id,
+					callbacks.event_name,
+					panic_message(&*payload)
+				);
+
+				if let PanicPolicy::Disable(after) = settings.panic_policy
+					&& panics >= after
+				{
+					warn!(
+						"disabling callback {} of {} after {panics} panics",
+						callback.id, callbacks.event_name
+					);
+					self.events
+						.write()
+						.unwrap()
+						.remove(TypeId::of::<E>(), monitor, callback.token);
+				}
+
+				None
+			}
+		}
 	}
 	/// Trigger an event
 	pub async fn trigger<E: Event>(
@@ -84,37 +350,131 @@ impl<CTX: 'static> Reactor<CTX> {
 		ctx: &CTX,
 		args: &mut E::Args<'_>,
 	) -> ControlFlow<E::Return> {
-		if let Some(callbacks) = self.events.get(&TypeId::of::<E>()) {
+		let (callbacks, monitors, settings) = {
+			let events = self.events.read().unwrap();
+			let event = TypeId::of::<E>();
+			(
+				events.callbacks.get(&event).cloned(),
+				events.monitors.get(&event).cloned(),
+				events.settings.clone(),
+			)
+		};
+
+		let mut result = ControlFlow::Continue(());
+		if let Some(callbacks) = callbacks {
 			for callback in callbacks.in_order() {
 				// Convert back to the real closure type
-				let closure: &Box<
-					dyn for<'a> Fn(
-							&'a CTX,
-							&'a mut E::Args<'_>,
-						) -> SmallBox<
-							dyn Future<Output = ControlFlow<E::Return>> + Send + 'a,
-							_SmallBoxSize,
-						> + Send
-						+ Sync
-						+ 'static,
-				> = callback.callback.downcast_ref().unwrap();
-
-				closure(ctx, args).await?;
+				let closure: &DynCallback<CTX, E> = callback.callback.downcast_ref().unwrap();
+
+				let future = closure(ctx, args);
+				let r = self
+					.run::<E, _>(&settings, false, &callbacks, callback, future)
+					.await;
+				if let Some(ControlFlow::Break(r)) = r {
+					result = ControlFlow::Break(r);
+					break;
+				}
 			}
 		}
 
-		ControlFlow::Continue(())
+		if let Some(monitors) = monitors {
+			for monitor in monitors.in_order() {
+				let closure: &DynMonitor<CTX, E> = monitor.callback.downcast_ref().unwrap();
+
+				let future = closure(ctx, args, &result);
+				self.run::<E, _>(&settings, true, &monitors, monitor, future)
+					.await;
+			}
+		}
+
+		result
+	}
+	/// Triggers the group `G` of the event, and then the event itself, unless the group callbacks
+	/// stopped it.
+	///
+	/// The group is only triggered if it has any callbacks or monitors, so the arguments are not built
+	/// for nothing.
+	pub async fn trigger_with_group<E: Member<G>, G: Event<Return = E::Return>>(
+		&self,
+		ctx: &CTX,
+		args: &mut E::Args<'_>,
+	) -> ControlFlow<E::Return> {
+		if self.has_callbacks::<G>() {
+			self.trigger::<G>(ctx, &mut E::group_args(args)).await?;
+		}
+
+		self.trigger::<E>(ctx, args).await
+	}
+	/// Checks if there are any callbacks or monitors registered for the event
+	pub fn has_callbacks<E: Event>(&self) -> bool {
+		let events = self.events.read().unwrap();
+		let event = TypeId::of::<E>();
+
+		[&events.callbacks, &events.monitors].iter().any(|map| {
+			map.get(&event)
+				.is_some_and(|callbacks| !callbacks.is_empty())
+		})
 	}
 	/// Returns a nested list of all registered callbacks: `["event type" -> ["callback id"]]`
+	///
+	/// This is a snapshot: callbacks added or removed afterwards don't change it.
 	pub fn list_callbacks(
 		&self,
-	) -> impl Iterator<Item = (&'static str, impl Iterator<Item = &str>)> {
-		self.events
-			.values()
-			.map(|event| (event.event_name, event.in_order().map(|c| c.id.as_str())))
+	) -> impl Iterator<Item = (&'static str, impl Iterator<Item = String> + use<CTX>)> + use<CTX> {
+		list(&self.events.read().unwrap().callbacks)
+	}
+	/// Returns a nested list of all registered monitors: `["event type" -> ["monitor id"]]`
+	pub fn list_monitors(
+		&self,
+	) -> impl Iterator<Item = (&'static str, impl Iterator<Item = String> + use<CTX>)> + use<CTX> {
+		list(&self.events.read().unwrap().monitors)
+	}
+	/// Enables profiling of all callbacks and monitors: their statistics are recorded (see [`Reactor::stats`])
+	/// and they run in `tracing` spans.
+	///
+	/// Calls that take longer than `slow_threshold` are logged as warnings.
+	/// If profiling was already enabled, the statistics are reset.
+	pub fn enable_profiling(&self, slow_threshold: Option<Duration>) {
+		self.events.write().unwrap().settings.profiler =
+			Some(Arc::new(Profiler::new(slow_threshold)));
+	}
+	/// Disables profiling, discarding the statistics
+	pub fn disable_profiling(&self) {
+		self.events.write().unwrap().settings.profiler = None;
+	}
+	/// Returns the statistics of all callbacks that were called while profiling was enabled:
+	/// `"event type" -> "callback id" -> stats`
+	pub fn stats(&self) -> BTreeMap<&'static str, BTreeMap<String, CallbackStats>> {
+		self.profiler().map(|p| p.callbacks()).unwrap_or_default()
+	}
+	/// Returns the statistics of all monitors, see [`Reactor::stats`]
+	pub fn monitor_stats(&self) -> BTreeMap<&'static str, BTreeMap<String, CallbackStats>> {
+		self.profiler().map(|p| p.monitors()).unwrap_or_default()
+	}
+	/// Sets what happens when a callback or a monitor panics. By default the panic is propagated.
+	pub fn set_panic_policy(&self, policy: PanicPolicy) {
+		self.events.write().unwrap().settings.panic_policy = policy;
+	}
+	fn profiler(&self) -> Option<Arc<Profiler>> {
+		self.events.read().unwrap().settings.profiler.clone()
 	}
 }
 
+// the callbacks are only copied if they are changed while the returned iterator is still in use
+fn list(
+	events: &BTreeMap<TypeId, Arc<Callbacks>>,
+) -> impl Iterator<Item = (&'static str, impl Iterator<Item = String> + use<>)> + use<> {
+	events
+		.values()
+		.cloned()
+		.collect::<Vec<_>>()
+		.into_iter()
+		.map(|event| {
+			let ids = event.in_order().map(|c| c.id.clone()).collect::<Vec<_>>();
+			(event.event_name, ids.into_iter())
+		})
+}
+
 impl<CTX: 'static> Default for Reactor<CTX> {
 	fn default() -> Self {
 		Self::new()
@@ -126,3 +486,47 @@ impl<CTX> std::fmt::Debug for Reactor<CTX> {
 		write!(f, "Closureslop Reactor")
 	}
 }
+
+impl CallbackHandle {
+	/// The id of the callback
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+}
+
+impl Drop for CallbackHandle {
+	fn drop(&mut self) {
+		// the reactor might be gone already
+		let Some(events) = self.events.upgrade() else {
+			return;
+		};
+
+		events
+			.write()
+			.unwrap()
+			.remove(self.event, self.monitor, self.token);
+	}
+}
+
+impl Events {
+	fn remove(&mut self, event: TypeId, monitor: bool, token: u64) {
+		let map = match monitor {
+			false => &mut self.callbacks,
+			true => &mut self.monitors,
+		};
+		if let Some(callbacks) = map.get_mut(&event) {
+			Arc::make_mut(callbacks).remove_callback(token);
+		}
+	}
+}
+
+impl Outcome for () {
+	fn is_break(&self) -> bool {
+		false
+	}
+}
+impl<R> Outcome for ControlFlow<R> {
+	fn is_break(&self) -> bool {
+		self.is_break()
+	}
+}
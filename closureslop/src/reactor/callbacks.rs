@@ -1,17 +1,26 @@
 use petgraph::graph::{DiGraph, NodeIndex};
-use std::any::Any;
+use std::{
+	any::Any,
+	sync::{Arc, atomic::AtomicU32},
+};
 
+#[derive(Clone)]
 pub(super) struct Callbacks {
 	pub(super) event_name: &'static str,
 	order: Vec<NodeIndex>,
 	graph: DiGraph<Callback, ()>,
 }
 
+#[derive(Clone)]
 pub(super) struct Callback {
 	pub(super) id: String,
-	pub(super) callback: Box<dyn Any + Send + Sync>,
+	pub(super) callback: Arc<dyn Any + Send + Sync>,
 	pub(super) must_come_after: Vec<String>,
 	pub(super) must_come_before: Vec<String>,
+	// how many times the callback panicked, shared between the copies
+	pub(super) panics: Arc<AtomicU32>,
+	// unique for every added callback, unlike the id
+	pub(super) token: u64,
 }
 
 impl Callbacks {
@@ -26,24 +35,47 @@ impl Callbacks {
 	pub(super) fn in_order(&self) -> impl Iterator<Item = &Callback> {
 		self.order.iter().map(|index| &self.graph[*index])
 	}
-	/// Panics if theres already a callback with the same id
-	pub(super) fn add_callback(&mut self, callback: Callback) {
+	pub(super) fn is_empty(&self) -> bool {
+		self.graph.node_count() == 0
+	}
+	/// Returns an error if theres already a callback with the same id or the order would become cyclic,
+	/// leaving the callbacks unchanged
+	pub(super) fn add_callback(&mut self, callback: Callback) -> Result<(), String> {
 		// make sure there isn't already a callback with this id
-		for other in self.graph.node_weights() {
-			if other.id == callback.id {
-				panic!(
-					"Callback with id {} already exists for this event",
-					callback.id
-				);
-			}
+		if self.find_with_id(&callback.id).is_some() {
+			return Err(format!(
+				"Callback with id {} already exists for this event",
+				callback.id
+			));
+		}
+
+		let index = self.graph.add_node(callback);
+		if let Err(e) = self.generate_order() {
+			// the last node is removed, so the other indices don't change
+			self.graph.remove_node(index);
+			self.generate_order()
+				.expect("the order was not cyclic before adding the callback");
+
+			return Err(e);
 		}
 
-		self.graph.add_node(callback);
-		self.generate_order();
+		Ok(())
+	}
+	/// Removes the callback with the given token, if there is one
+	pub(super) fn remove_callback(&mut self, token: u64) {
+		if let Some(index) = self
+			.graph
+			.node_indices()
+			.find(|callback| self.graph[*callback].token == token)
+		{
+			self.graph.remove_node(index);
+			self.generate_order()
+				.expect("removing a callback can't make the order cyclic");
+		}
 	}
 	/// Generates the order of callbacks to be executed
-	/// panics if the order is cyclic
-	pub(super) fn generate_order(&mut self) {
+	/// returns an error if the order is cyclic
+	fn generate_order(&mut self) -> Result<(), String> {
 		// regenerate all edges and then sort the graph topologically, saving the order
 		self.graph.clear_edges();
 
@@ -74,9 +106,15 @@ impl Callbacks {
 		// all edges are added, now sort the graph
 		self.order = match petgraph::algo::toposort(&self.graph, None) {
 			Ok(order) => order,
-			Err(e) => panic!("Cyclic dependencies detected in callbacks order: {e:?}"),
+			Err(e) => {
+				return Err(format!(
+					"Cyclic dependencies detected in callbacks order: {e:?}"
+				));
+			}
 		};
 		self.order.reverse();
+
+		Ok(())
 	}
 	/// Finds a callback with the given id, returning it's index
 	fn find_with_id(&self, id: &str) -> Option<NodeIndex> {
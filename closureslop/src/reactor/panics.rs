@@ -0,0 +1,44 @@
+use std::{
+	any::Any,
+	future::poll_fn,
+	panic::{self, AssertUnwindSafe},
+	pin::pin,
+	task::Poll,
+};
+
+/// What to do when a callback or a monitor panics, see [`Reactor::set_panic_policy`][super::Reactor::set_panic_policy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+	/// Let the panic unwind out of [`Reactor::trigger`][super::Reactor::trigger].
+	#[default]
+	Propagate,
+	/// Log the panic and go on with the next callback, as if the one that panicked returned
+	/// `ControlFlow::Continue`.
+	Skip,
+	/// Like [`Skip`][PanicPolicy::Skip], but remove the callback from the reactor once it has
+	/// panicked this many times.
+	Disable(u32),
+}
+
+/// Polls the future, catching any panics
+pub(super) async fn catch_unwind<F: Future>(future: F) -> Result<F::Output, Box<dyn Any + Send>> {
+	let mut future = pin!(future);
+
+	poll_fn(
+		|cx| match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+			Ok(poll) => poll.map(Ok),
+			Err(payload) => Poll::Ready(Err(payload)),
+		},
+	)
+	.await
+}
+
+pub(super) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+	if let Some(msg) = payload.downcast_ref::<&str>() {
+		msg
+	} else if let Some(msg) = payload.downcast_ref::<String>() {
+		msg
+	} else {
+		"unknown panic"
+	}
+}
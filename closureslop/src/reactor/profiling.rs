@@ -0,0 +1,93 @@
+use super::Outcome;
+use std::{
+	collections::BTreeMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+use tracing::{Instrument, debug_span, warn};
+
+/// Statistics of a single callback, see [`Reactor::stats`][super::Reactor::stats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallbackStats {
+	/// How many times the callback was called
+	pub calls: u64,
+	/// How many times the callback stopped the event
+	pub breaks: u64,
+	/// The total time spent in the callback, including awaiting
+	pub total: Duration,
+	/// The longest time a single call took
+	pub max: Duration,
+}
+
+pub(super) type StatsMap = BTreeMap<&'static str, BTreeMap<String, CallbackStats>>;
+
+pub(super) struct Profiler {
+	slow_threshold: Option<Duration>,
+	callbacks: Mutex<StatsMap>,
+	monitors: Mutex<StatsMap>,
+}
+
+impl CallbackStats {
+	/// The average time a single call took
+	pub fn average(&self) -> Duration {
+		match self.calls {
+			0 => Duration::ZERO,
+			calls => Duration::from_nanos((self.total.as_nanos() / calls as u128) as u64),
+		}
+	}
+}
+
+impl Profiler {
+	pub(super) fn new(slow_threshold: Option<Duration>) -> Self {
+		Self {
+			slow_threshold,
+			callbacks: Mutex::new(BTreeMap::new()),
+			monitors: Mutex::new(BTreeMap::new()),
+		}
+	}
+	/// Runs the future of a callback in a span, recording how long it took
+	pub(super) async fn measure<F: Future<Output: Outcome>>(
+		&self,
+		monitor: bool,
+		event: &'static str,
+		id: &str,
+		future: F,
+	) -> F::Output {
+		let start = Instant::now();
+		let output = future
+			.instrument(debug_span!("callback", event, id, monitor))
+			.await;
+		let elapsed = start.elapsed();
+
+		if let Some(threshold) = self.slow_threshold
+			&& elapsed > threshold
+		{
+			warn!("callback {id} of {event} took {elapsed:?}");
+		}
+
+		let mut stats = match monitor {
+			false => self.callbacks.lock().unwrap(),
+			true => self.monitors.lock().unwrap(),
+		};
+		let event_stats = stats.entry(event).or_default();
+		// dont allocate the id every time
+		if !event_stats.contains_key(id) {
+			event_stats.insert(id.to_owned(), CallbackStats::default());
+		}
+		let stats = event_stats.get_mut(id).unwrap();
+		stats.calls += 1;
+		stats.total += elapsed;
+		stats.max = stats.max.max(elapsed);
+		if output.is_break() {
+			stats.breaks += 1;
+		}
+
+		output
+	}
+	pub(super) fn callbacks(&self) -> StatsMap {
+		self.callbacks.lock().unwrap().clone()
+	}
+	pub(super) fn monitors(&self) -> StatsMap {
+		self.monitors.lock().unwrap().clone()
+	}
+}
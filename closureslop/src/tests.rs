@@ -1,6 +1,6 @@
 use super::*;
 use smallbox::SmallBox;
-use std::ops::ControlFlow;
+use std::{ops::ControlFlow, time::Duration};
 
 #[pollster::test]
 async fn simple() {
@@ -88,3 +88,294 @@ async fn ordered() {
 	let _ = reactor.trigger::<MyEvent>(&(), &mut x).await;
 	assert_eq!(&x, &['C', 'A', 'D', 'E', 'B']);
 }
+
+#[pollster::test]
+async fn runtime() {
+	let mut reactor = Reactor::<()>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = ();
+	}
+
+	add_callback!(reactor, MyEvent => "A" => |_ctx, args| SmallBox::new(async move {
+		args.push('A');
+		ControlFlow::Continue(())
+	}));
+	let b = add_runtime_callback!(reactor, MyEvent => "B" => |_ctx, args| SmallBox::new(async move {
+			args.push('B');
+			ControlFlow::Continue(())
+		}), after: "closureslop:A");
+	let c = add_runtime_callback!(reactor, MyEvent => "C" => |_ctx, args| SmallBox::new(async move {
+			args.push('C');
+			ControlFlow::Continue(())
+		}), before: "closureslop:A");
+
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&(), &mut x).await;
+	assert_eq!(&x, &['C', 'A', 'B']);
+
+	drop(c);
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&(), &mut x).await;
+	assert_eq!(&x, &['A', 'B']);
+
+	// the id can be used again after removing
+	drop(b);
+	let _b = add_runtime_callback!(reactor, MyEvent => "B" => |_ctx, args| SmallBox::new(async move {
+			args.push('B');
+			ControlFlow::Continue(())
+		}), before: "closureslop:A");
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&(), &mut x).await;
+	assert_eq!(&x, &['B', 'A']);
+}
+
+#[pollster::test]
+async fn runtime_during_trigger() {
+	use std::sync::Mutex;
+
+	// the handle of the second callback
+	let mut reactor = Reactor::<Mutex<Option<CallbackHandle>>>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = ();
+	}
+
+	add_callback!(reactor, MyEvent => "A" => |ctx, args| SmallBox::new(async move {
+		// removing a callback while the event is being triggered
+		drop(ctx.lock().unwrap().take());
+		args.push('A');
+		ControlFlow::Continue(())
+	}));
+	let b = add_runtime_callback!(reactor, MyEvent => "B" => |_ctx, args| SmallBox::new(async move {
+			args.push('B');
+			ControlFlow::Continue(())
+		}), after: "closureslop:A");
+	let ctx = Mutex::new(Some(b));
+
+	// still runs, since it was removed after the trigger started
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&ctx, &mut x).await;
+	assert_eq!(&x, &['A', 'B']);
+
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&ctx, &mut x).await;
+	assert_eq!(&x, &['A']);
+}
+
+#[pollster::test]
+async fn monitors() {
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	// set when the monitor runs
+	let mut reactor = Reactor::<AtomicBool>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = u32;
+	}
+
+	add_callback!(reactor, MyEvent => "A" => |_ctx, args| SmallBox::new(async move {
+		args.push('A');
+		ControlFlow::Break(7)
+	}));
+	add_callback!(reactor, MyEvent => "B" => |_ctx, args| SmallBox::new(async move {
+			args.push('B');
+			ControlFlow::Continue(())
+		}), after: "closureslop:A");
+	// monitors have their own ids and order
+	add_monitor!(reactor, MyEvent => "A" => |ctx: &AtomicBool, args: &Vec<char>, result: &ControlFlow<u32>| SmallBox::new(async move {
+		ctx.store(true, Ordering::Relaxed);
+		assert_eq!(args, &['A']);
+		assert_eq!(result, &ControlFlow::Break(7));
+	}));
+
+	let ctx = AtomicBool::new(false);
+	let mut x = Vec::new();
+	assert_eq!(
+		reactor.trigger::<MyEvent>(&ctx, &mut x).await,
+		ControlFlow::Break(7)
+	);
+	assert!(ctx.load(Ordering::Relaxed));
+	assert_eq!(
+		collect(reactor.list_monitors()),
+		[(
+			std::any::type_name::<MyEvent>(),
+			vec!["closureslop:A".to_owned()]
+		)]
+	);
+}
+
+#[pollster::test]
+async fn groups() {
+	let mut reactor = Reactor::<()>::new();
+
+	struct Group;
+	impl Event for Group {
+		type Args<'a> = (&'static str, &'a Vec<char>);
+		type Return = ();
+	}
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = ();
+	}
+	impl Member<Group> for MyEvent {
+		fn group_args<'a>(args: &'a Vec<char>) -> (&'static str, &'a Vec<char>) {
+			("my event", args)
+		}
+	}
+
+	add_callback!(reactor, MyEvent => "member" => |_ctx, args| SmallBox::new(async move {
+		args.push('M');
+		ControlFlow::Continue(())
+	}));
+
+	let mut x = Vec::new();
+	let _ = reactor
+		.trigger_with_group::<MyEvent, Group>(&(), &mut x)
+		.await;
+	assert_eq!(&x, &['M']);
+
+	// the group sees the event first and can stop it
+	let handle = add_runtime_callback!(reactor, Group => "group" => |_ctx, &mut (name, args)| SmallBox::new(async move {
+		assert_eq!(name, "my event");
+		assert_eq!(args, &['M']);
+		ControlFlow::Break(())
+	}));
+	assert!(reactor.has_callbacks::<Group>());
+
+	let result = reactor
+		.trigger_with_group::<MyEvent, Group>(&(), &mut x)
+		.await;
+	assert_eq!(result, ControlFlow::Break(()));
+	assert_eq!(&x, &['M']);
+
+	drop(handle);
+	assert!(!reactor.has_callbacks::<Group>());
+}
+
+#[pollster::test]
+async fn profiling() {
+	let mut reactor = Reactor::<()>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = ();
+		type Return = ();
+	}
+
+	add_callback!(reactor, MyEvent => "slow" => |_ctx, _args| SmallBox::new(async move {
+		std::thread::sleep(Duration::from_millis(10));
+		ControlFlow::Continue(())
+	}));
+	add_callback!(reactor, MyEvent => "stop" => |_ctx, _args| SmallBox::new(async move {
+			ControlFlow::Break(())
+		}), after: "closureslop:slow");
+	add_monitor!(reactor, MyEvent => "monitor" => |_ctx, _args, _result| SmallBox::new(async move {}));
+
+	// not recorded before profiling is enabled
+	let _ = reactor.trigger::<MyEvent>(&(), &mut ()).await;
+	assert!(reactor.stats().is_empty());
+
+	reactor.enable_profiling(Some(Duration::from_millis(5)));
+	for _ in 0..2 {
+		let _ = reactor.trigger::<MyEvent>(&(), &mut ()).await;
+	}
+
+	let stats = &reactor.stats()[std::any::type_name::<MyEvent>()];
+	let slow = stats["closureslop:slow"];
+	assert_eq!((slow.calls, slow.breaks), (2, 0));
+	assert!(slow.max >= Duration::from_millis(10));
+	assert!(slow.average() >= Duration::from_millis(10) && slow.average() <= slow.max);
+	let stop = stats["closureslop:stop"];
+	assert_eq!((stop.calls, stop.breaks), (2, 2));
+
+	let monitor_stats = &reactor.monitor_stats()[std::any::type_name::<MyEvent>()];
+	assert_eq!(monitor_stats["closureslop:monitor"].calls, 2);
+
+	reactor.disable_profiling();
+	assert!(reactor.stats().is_empty());
+}
+
+#[pollster::test]
+async fn panics() {
+	let mut reactor = Reactor::<()>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = ();
+	}
+
+	add_callback!(reactor, MyEvent => "A" => |_ctx, args| SmallBox::new(async move {
+		args.push('A');
+		panic!("oops");
+	}));
+	add_callback!(reactor, MyEvent => "B" => |_ctx, args| SmallBox::new(async move {
+			args.push('B');
+			ControlFlow::Continue(())
+		}), after: "closureslop:A");
+
+	// panics by default
+	let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+		pollster::block_on(reactor.trigger::<MyEvent>(&(), &mut Vec::new()))
+	}));
+	assert!(result.is_err());
+
+	reactor.set_panic_policy(PanicPolicy::Disable(2));
+	let mut x = Vec::new();
+	for _ in 0..3 {
+		let result = reactor.trigger::<MyEvent>(&(), &mut x).await;
+		assert_eq!(result, ControlFlow::Continue(()));
+	}
+	// the panicking callback is skipped and then removed
+	assert_eq!(&x, &['A', 'B', 'A', 'B', 'B']);
+	assert_eq!(
+		collect(reactor.list_callbacks()),
+		[(
+			std::any::type_name::<MyEvent>(),
+			vec!["closureslop:B".to_owned()]
+		)]
+	);
+}
+
+fn collect(
+	list: impl Iterator<Item = (&'static str, impl Iterator<Item = String>)>,
+) -> Vec<(&'static str, Vec<String>)> {
+	list.map(|(event, ids)| (event, ids.collect())).collect()
+}
+
+#[pollster::test]
+async fn stale_handle() {
+	let reactor = Reactor::<()>::new();
+
+	struct MyEvent;
+	impl Event for MyEvent {
+		type Args<'a> = Vec<char>;
+		type Return = ();
+	}
+
+	reactor.set_panic_policy(PanicPolicy::Disable(1));
+	let old = add_runtime_callback!(reactor, MyEvent => "A" => |_ctx, _args| SmallBox::new(async move {
+		panic!("oops");
+	}));
+	let _ = reactor.trigger::<MyEvent>(&(), &mut Vec::new()).await;
+	assert!(!reactor.has_callbacks::<MyEvent>());
+
+	// the handle of the disabled callback doesn't remove the new one with the same id
+	let _new = add_runtime_callback!(reactor, MyEvent => "A" => |_ctx, args| SmallBox::new(async move {
+		args.push('A');
+		ControlFlow::Continue(())
+	}));
+	drop(old);
+
+	let mut x = Vec::new();
+	let _ = reactor.trigger::<MyEvent>(&(), &mut x).await;
+	assert_eq!(&x, &['A']);
+}
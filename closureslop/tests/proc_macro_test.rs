@@ -1,5 +1,8 @@
 use closureslop::{Event, Reactor, callback, init, reg};
-use std::ops::ControlFlow;
+use std::{
+	ops::ControlFlow,
+	sync::atomic::{AtomicBool, Ordering},
+};
 
 init!(ctx: ());
 init!(group: "1", ctx: ());
@@ -7,6 +10,7 @@ init!(group: "2", ctx: ());
 init!(group: "ordered", ctx: ());
 init!(group: "m1", ctx: ());
 init!(group: "m2", ctx: ());
+init!(group: "monitored", ctx: ());
 
 struct Adder;
 impl Event for Adder {
@@ -106,3 +110,28 @@ async fn multiple_groups() {
 
 	assert_eq!(acc, "yeye")
 }
+
+#[pollster::test]
+async fn monitor() {
+	let mut reactor = Reactor::new();
+
+	#[callback(event: Adder, group: "monitored")]
+	async fn stopper(_ctx: &(), acc: &mut String) -> ControlFlow<()> {
+		*acc += "s";
+		ControlFlow::Break(())
+	}
+	static CHECKED: AtomicBool = AtomicBool::new(false);
+	#[callback(event: Adder, group: "monitored", monitor)]
+	async fn checker(_ctx: &(), acc: &String, result: &ControlFlow<()>) {
+		CHECKED.store(true, Ordering::Relaxed);
+		assert_eq!(acc, "s");
+		assert_eq!(result, &ControlFlow::Break(()));
+	}
+
+	reg!(to: reactor, group: "monitored");
+
+	let mut acc = String::new();
+	let _ = reactor.trigger::<Adder>(&(), &mut acc).await;
+
+	assert!(CHECKED.load(Ordering::Relaxed));
+}
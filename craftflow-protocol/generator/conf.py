@@ -18,7 +18,7 @@ PACKETS = {
         "login": ["login_start", "encryption_begin", "login_plugin_response", "login_acknowledged"],
         "configuration": ["settings", "custom_payload", "finish_configuration", "keep_alive", "pong",
                           "resource_pack_receive", "select_known_packs"],
-        "play": ["keep_alive", "teleport_confirm"],
+        "play": ["keep_alive", "teleport_confirm", "configuration_acknowledged"],
     },
     "s2c": {
         "status": ["server_info", "ping"],
@@ -26,7 +26,7 @@ PACKETS = {
         "configuration": ["custom_payload", "disconnect", "finish_configuration", "keep_alive", "ping",
                           "registry_data", "remove_resource_pack", "add_resource_pack", "feature_flags",
                           "tags", "reset_chat", "select_known_packs"],
-        "play": ["keep_alive", "login", "position"],
+        "play": ["keep_alive", "login", "position", "kick_disconnect", "start_configuration"],
     }
 }
 TYPES = ["tags", "s2c.play.SpawnInfo", "s2c.play.PositionUpdateRelatives"]
@@ -0,0 +1,9 @@
+// [
+//     "container",
+//     []
+// ]
+
+mcp! {
+	#[derive(Debug, PartialEq, Clone, Hash, PartialOrd, Ord, Eq)]
+	pub struct ConfigurationAcknowledgedV764;
+}
@@ -0,0 +1,16 @@
+// [
+//     "container",
+//     [
+//         {
+//             "name": "reason",
+//             "type": "string"
+//         }
+//     ]
+// ]
+
+mcp! {
+	#[derive(Debug, PartialEq, Clone, Hash, PartialOrd, Ord, Eq)]
+	pub struct KickDisconnectV5 {
+		pub reason: (String),
+	}
+}
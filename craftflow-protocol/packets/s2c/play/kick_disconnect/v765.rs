@@ -0,0 +1,16 @@
+// [
+//     "container",
+//     [
+//         {
+//             "name": "reason",
+//             "type": "anonymousNbt"
+//         }
+//     ]
+// ]
+
+mcp! {
+	#[derive(Debug, PartialEq, Clone)]
+	pub struct KickDisconnectV765 {
+		pub reason: (Nbt),
+	}
+}
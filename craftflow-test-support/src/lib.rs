@@ -0,0 +1,164 @@
+//! Helpers shared by the tests that drive whole connections over in-memory streams,
+//! without opening any sockets
+
+use craftflow::{ConnId, CraftFlow, callback, packet_events::Packet};
+use craftflow_protocol::{
+	PacketRead, PacketWrite, SUPPORTED_VERSIONS,
+	c2s::{
+		handshaking::set_protocol::v5::SetProtocolV5,
+		login::{LoginStart, login_start::v764::LoginStartV764},
+	},
+	s2c::login::{
+		SuccessBuilder,
+		success::{v759::SuccessV759, v766::SuccessV766},
+	},
+};
+use std::{
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	ops::ControlFlow,
+	sync::Arc,
+};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, duplex},
+	spawn,
+	task::JoinHandle,
+};
+
+craftflow::init!();
+
+/// The protocol version that the test clients use
+pub const VERSION: u32 = SUPPORTED_VERSIONS[SUPPORTED_VERSIONS.len() - 1];
+/// The UUID that the test clients log in with
+pub const UUID: u128 = 0x0123_4567_89ab_cdef;
+
+/// Registers a callback that answers every login start with a login success,
+/// so that the connection moves on to configuration
+pub fn register(craftflow: &mut CraftFlow) {
+	craftflow::reg!(to: &mut craftflow.reactor);
+}
+
+#[callback(event: Packet<LoginStart>)]
+async fn login_start(
+	cf: &Arc<CraftFlow>,
+	(conn_id, request): &mut (ConnId, LoginStart),
+) -> ControlFlow<()> {
+	let LoginStart::V764(request) = request else {
+		unreachable!()
+	};
+	let (uuid, username) = (request.player_uuid, request.username.clone());
+
+	cf.build_packet(*conn_id, |b| match b {
+		SuccessBuilder::V759(p) => p(SuccessV759 {
+			uuid,
+			username,
+			properties: Vec::new(),
+		}),
+		SuccessBuilder::V766(p) => p(SuccessV766 {
+			uuid,
+			username,
+			properties: Vec::new(),
+			strict_error_handling: false,
+		}),
+		_ => unreachable!(),
+	})
+	.await;
+
+	ControlFlow::Continue(())
+}
+
+/// Starts handling the server side of a new in-memory connection, returning the client side
+pub fn connect(craftflow: &Arc<CraftFlow>) -> (DuplexStream, JoinHandle<anyhow::Result<()>>) {
+	connect_from(craftflow, Ipv4Addr::LOCALHOST.into())
+}
+
+/// Same as [`connect`], but the connection comes from the given address
+pub fn connect_from(
+	craftflow: &Arc<CraftFlow>,
+	ip: IpAddr,
+) -> (DuplexStream, JoinHandle<anyhow::Result<()>>) {
+	let (client, server) = duplex(64 * 1024);
+	let peer_addr = SocketAddr::new(ip, 12345);
+
+	let craftflow = Arc::clone(craftflow);
+	let task = spawn(async move { craftflow.accept_stream(server, peer_addr).await });
+
+	(client, task)
+}
+
+/// Sends a handshake to localhost with the given next state
+pub async fn handshake(client: &mut (impl AsyncWrite + Unpin), next_state: i32) {
+	let handshake = SetProtocolV5 {
+		protocol_version: VERSION as i32,
+		server_host: "localhost".to_owned(),
+		server_port: 25565,
+		next_state,
+	};
+	write_packet(client, &handshake).await;
+}
+
+/// Sends a handshake and a login start as `player` with [`UUID`]
+pub async fn login(client: &mut (impl AsyncWrite + Unpin)) {
+	handshake(client, 2).await;
+	write_packet(
+		client,
+		&LoginStartV764 {
+			username: "player".to_owned(),
+			player_uuid: UUID,
+		},
+	)
+	.await;
+}
+
+/// Writes an uncompressed and unencrypted packet
+pub async fn write_packet(client: &mut (impl AsyncWrite + Unpin), packet: &impl PacketWrite) {
+	let mut data = Vec::new();
+	packet.packet_write(&mut data, VERSION);
+
+	write_frame(client, &data).await;
+}
+
+/// Writes the length prefixed data
+pub async fn write_frame(client: &mut (impl AsyncWrite + Unpin), data: &[u8]) {
+	let mut len = data.len() as u32;
+	loop {
+		let byte = (len & 0x7f) as u8;
+		len >>= 7;
+		if len == 0 {
+			client.write_u8(byte).await.unwrap();
+			break;
+		}
+		client.write_u8(byte | 0x80).await.unwrap();
+	}
+	client.write_all(data).await.unwrap();
+}
+
+/// Reads an uncompressed and unencrypted packet
+pub async fn read_packet<P: for<'a> PacketRead<'a>>(client: &mut (impl AsyncRead + Unpin)) -> P {
+	parse_packet(&read_frame(client).await)
+}
+
+/// Reads the length prefixed data
+pub async fn read_frame(client: &mut (impl AsyncRead + Unpin)) -> Vec<u8> {
+	let mut len = 0;
+	for i in 0.. {
+		let byte = client.read_u8().await.unwrap();
+		len |= ((byte & 0x7f) as usize) << (7 * i);
+		if byte & 0x80 == 0 {
+			break;
+		}
+	}
+
+	let mut data = vec![0; len];
+	client.read_exact(&mut data).await.unwrap();
+
+	data
+}
+
+/// Parses a whole packet, panicking if anything is left over
+pub fn parse_packet<P: for<'a> PacketRead<'a>>(data: &[u8]) -> P {
+	let mut input = data;
+	let packet = P::packet_read(&mut input, VERSION).unwrap();
+	assert!(input.is_empty(), "packet not fully read");
+
+	packet
+}
@@ -0,0 +1,289 @@
+//! Throughput of the connection pipeline (framing, compression and encryption) over in-memory streams
+//!
+//! `cargo bench -p craftflow --bench pipeline`
+
+use aes::cipher::{AsyncStreamCipher, KeyIvInit};
+use craftflow::{ConnId, CraftFlow, callback, packet_events::Packet, reg};
+use craftflow_protocol::{
+	PacketWrite,
+	c2s::{
+		handshaking::set_protocol::v5::SetProtocolV5,
+		login::{
+			LoginPluginResponse, LoginStart, login_plugin_response::v393::LoginPluginResponseV393,
+			login_start::v764::LoginStartV764,
+		},
+	},
+	disabled_versions,
+	s2c::login::{LoginPluginRequestBuilder, login_plugin_request::v393::LoginPluginRequestV393},
+};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use flate2::{Compression, write::ZlibEncoder};
+use std::{
+	io::Write,
+	net::{Ipv4Addr, SocketAddr},
+	ops::ControlFlow,
+	sync::{
+		Arc,
+		atomic::{AtomicUsize, Ordering},
+	},
+	time::{Duration, Instant},
+};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex},
+	runtime::Runtime,
+	spawn,
+	sync::Notify,
+};
+
+craftflow::init!();
+
+const VERSION: u32 = 769;
+const KEY: [u8; 16] = *b"craftflow bench!";
+const COMPRESSION_THRESHOLD: usize = 256;
+const PACKETS: usize = 256;
+const PAYLOAD_SIZE: usize = 4 * 1024;
+
+/// Set up as a module, so the callbacks can access it
+struct Bench {
+	compression: bool,
+	encryption: bool,
+	// notified when the connection has compression and encryption enabled
+	ready: Notify,
+	received: AtomicUsize,
+	// notified when all packets are received
+	done: Notify,
+}
+
+#[callback(event: Packet<LoginStart>)]
+async fn login_start(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, _): &mut (ConnId, LoginStart),
+) -> ControlFlow<()> {
+	let bench = cf.modules.get::<Bench>();
+	let conn = cf.get(conn_id);
+
+	if bench.compression {
+		conn.set_compression_threshold(COMPRESSION_THRESHOLD);
+	}
+	if bench.encryption {
+		conn.set_encryption(KEY);
+	}
+	bench.ready.notify_one();
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Packet<LoginPluginResponse>)]
+async fn plugin_response(
+	cf: &Arc<CraftFlow>,
+	_: &mut (ConnId, LoginPluginResponse),
+) -> ControlFlow<()> {
+	let bench = cf.modules.get::<Bench>();
+
+	if bench.received.fetch_add(1, Ordering::Relaxed) + 1 == PACKETS {
+		bench.done.notify_one();
+	}
+
+	ControlFlow::Continue(())
+}
+
+fn payload() -> Vec<u8> {
+	// somewhat compressible, like real data
+	(0..PAYLOAD_SIZE).map(|i| (i % 251 / 7) as u8).collect()
+}
+
+fn craftflow(compression: bool, encryption: bool) -> Arc<CraftFlow> {
+	let mut craftflow = CraftFlow::new();
+	craftflow.modules.register(Bench {
+		compression,
+		encryption,
+		ready: Notify::new(),
+		received: AtomicUsize::new(0),
+		done: Notify::new(),
+	});
+	reg!(to: &mut craftflow.reactor);
+
+	Arc::new(craftflow)
+}
+
+/// Connects a new client and logs in until compression and encryption are enabled
+async fn connect(craftflow: &Arc<CraftFlow>) -> (DuplexStream, ConnId) {
+	let (mut client, server) = duplex(256 * 1024);
+	let peer_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345);
+
+	let craftflow_clone = Arc::clone(craftflow);
+	spawn(async move { craftflow_clone.accept_stream(server, peer_addr).await });
+
+	let mut bytes = Vec::new();
+	let handshake = SetProtocolV5 {
+		protocol_version: VERSION as i32,
+		server_host: "localhost".to_owned(),
+		server_port: 25565,
+		next_state: 2,
+	};
+	let login_start = LoginStartV764 {
+		username: "bench".to_owned(),
+		player_uuid: 0,
+	};
+	frame(&mut bytes, None, &handshake);
+	frame(&mut bytes, None, &login_start);
+	client.write_all(&bytes).await.unwrap();
+
+	craftflow.modules.get::<Bench>().ready.notified().await;
+
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+
+	(client, conn_id)
+}
+
+/// Serializes a packet the way a client would
+fn frame(output: &mut Vec<u8>, compression: Option<usize>, packet: &impl PacketWrite) {
+	let mut data = Vec::new();
+	packet.packet_write(&mut data, VERSION);
+
+	match compression {
+		None => {
+			write_varint(output, data.len());
+			output.extend_from_slice(&data);
+		}
+		Some(threshold) if data.len() < threshold => {
+			write_varint(output, data.len() + 1);
+			output.push(0);
+			output.extend_from_slice(&data);
+		}
+		Some(_) => {
+			let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+			zlib.write_all(&data).unwrap();
+			let compressed = zlib.finish().unwrap();
+
+			let mut uncompressed_len = Vec::new();
+			write_varint(&mut uncompressed_len, data.len());
+
+			write_varint(output, uncompressed_len.len() + compressed.len());
+			output.extend_from_slice(&uncompressed_len);
+			output.extend_from_slice(&compressed);
+		}
+	}
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+		if value == 0 {
+			output.push(byte);
+			break;
+		}
+		output.push(byte | 0x80);
+	}
+}
+
+/// Server sending packets to the client
+async fn write(craftflow: &Arc<CraftFlow>) -> Duration {
+	let (mut client, conn_id) = connect(craftflow).await;
+	let conn = craftflow.get(conn_id);
+	let data = payload();
+
+	let start = Instant::now();
+
+	let reader = spawn(async move {
+		let mut received = Vec::new();
+		client.read_to_end(&mut received).await.unwrap();
+	});
+
+	for message_id in 0..PACKETS as i32 {
+		let data = data.clone();
+		craftflow
+			.build_packet(conn_id, |b| match b {
+				LoginPluginRequestBuilder::V393(p) => p(LoginPluginRequestV393 {
+					message_id,
+					channel: "craftflow:bench".to_owned(),
+					data,
+				}),
+				disabled_versions!(s2c::login::LoginPluginRequestBuilder) => unreachable!(),
+			})
+			.await;
+	}
+
+	// dropping the connection flushes all queued packets and closes the stream
+	craftflow.disconnect(conn_id).await;
+	drop(conn);
+	reader.await.unwrap();
+
+	start.elapsed()
+}
+
+/// Client sending packets to the server
+async fn read(craftflow: &Arc<CraftFlow>) -> Duration {
+	let bench = craftflow.modules.get::<Bench>();
+	let (mut client, conn_id) = connect(craftflow).await;
+	let compression = bench.compression.then_some(COMPRESSION_THRESHOLD);
+
+	let mut bytes = Vec::new();
+	for message_id in 0..PACKETS as i32 {
+		let packet = LoginPluginResponseV393 {
+			message_id,
+			data: Some(payload()),
+		};
+		frame(&mut bytes, compression, &packet);
+	}
+	if bench.encryption {
+		cfb8::Encryptor::<aes::Aes128>::new(&KEY.into(), &KEY.into()).encrypt(&mut bytes);
+	}
+	bench.received.store(0, Ordering::Relaxed);
+
+	let start = Instant::now();
+
+	client.write_all(&bytes).await.unwrap();
+	bench.done.notified().await;
+
+	let elapsed = start.elapsed();
+
+	craftflow.disconnect(conn_id).await;
+
+	elapsed
+}
+
+fn pipeline(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+	let mut group = c.benchmark_group("pipeline");
+	group.throughput(Throughput::BytesDecimal((PACKETS * PAYLOAD_SIZE) as u64));
+
+	for (name, compression, encryption) in [
+		("plain", false, false),
+		("compressed", true, false),
+		("encrypted", false, true),
+		("compressed+encrypted", true, true),
+	] {
+		let craftflow = craftflow(compression, encryption);
+
+		group.bench_function(BenchmarkId::new("write", name), |b| {
+			b.iter_custom(|iters| {
+				runtime.block_on(async {
+					let mut total = Duration::ZERO;
+					for _ in 0..iters {
+						total += write(&craftflow).await;
+					}
+					total
+				})
+			})
+		});
+
+		group.bench_function(BenchmarkId::new("read", name), |b| {
+			b.iter_custom(|iters| {
+				runtime.block_on(async {
+					let mut total = Duration::ZERO;
+					for _ in 0..iters {
+						total += read(&craftflow).await;
+					}
+					total
+				})
+			})
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
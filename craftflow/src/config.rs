@@ -0,0 +1,306 @@
+use std::{
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	time::Duration,
+};
+use tokio::net::TcpListener;
+
+pub use closureslop::PanicPolicy;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// The address that will be used if no listeners are configured
+pub const DEFAULT_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 25565);
+/// The default time to wait for clients to be disconnected when shutting down
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default maximum number of packets queued for sending to a single client
+pub const DEFAULT_MAX_QUEUED_PACKETS: usize = 16;
+
+/// The default maximum number of bytes of packets written to a client at once
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 64 * 1024;
+
+/// The default maximum number of packets held until a client reaches their state
+pub const DEFAULT_MAX_PENDING_PACKETS: usize = 1024;
+/// The default time to wait for a client to reach the state of held packets
+pub const DEFAULT_PENDING_PACKETS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default zlib compression level
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+/// The highest supported compression level
+#[cfg(not(feature = "libdeflate"))]
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
+/// The highest supported compression level
+#[cfg(feature = "libdeflate")]
+pub const MAX_COMPRESSION_LEVEL: u32 = 12;
+
+/// The default size in bytes of packets from which compression and decompression is done on the
+/// blocking thread pool
+pub const DEFAULT_OFFLOAD_COMPRESSION_SIZE: usize = 64 * 1024;
+
+/// The configuration of a [`CraftFlow`][crate::CraftFlow] server.
+///
+/// ```
+/// # use craftflow::{CraftFlow, config::Config};
+/// let config = Config::new()
+/// 	.bind(([0, 0, 0, 0], 25565))
+/// 	.bind("[::1]:25566".parse::<std::net::SocketAddr>().unwrap());
+///
+/// let craftflow = CraftFlow::with_config(config);
+/// ```
+pub struct Config {
+	pub(crate) listeners: Vec<ListenerConfig>,
+	pub(crate) shutdown_timeout: Duration,
+	// None if PROXY protocol disabled
+	pub(crate) proxy_protocol: Option<Vec<IpAddr>>,
+	pub(crate) outbound_queue: OutboundQueueConfig,
+	pub(crate) max_batch_size: usize,
+	pub(crate) max_batch_latency: Duration,
+	pub(crate) max_pending_packets: usize,
+	pub(crate) pending_packets_timeout: Duration,
+	pub(crate) compression_level: u32,
+	// None if compression is never offloaded
+	pub(crate) offload_compression_size: Option<usize>,
+	pub(crate) strict_protocol: bool,
+	pub(crate) callback_panic_policy: PanicPolicy,
+}
+
+/// Limits of the queue of packets waiting to be sent to a client, and what to do when a client
+/// doesn't read them fast enough.
+///
+/// ```
+/// # use craftflow::config::{OutboundQueueConfig, OverflowPolicy};
+/// // disconnect clients that have more than 1 MiB of packets waiting to be sent
+/// let queue = OutboundQueueConfig::new()
+/// 	.max_packets(1024)
+/// 	.max_bytes(1024 * 1024)
+/// 	.on_overflow(OverflowPolicy::Disconnect);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundQueueConfig {
+	pub(crate) max_packets: usize,
+	pub(crate) max_bytes: Option<usize>,
+	pub(crate) overflow: OverflowPolicy,
+}
+
+/// What to do when the outbound queue of a client is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+	/// Wait until there is space in the queue before sending.
+	#[default]
+	Block,
+	/// Drop the oldest queued packets to make space for new ones.
+	/// Only use this if the client can do without some of the packets.
+	DropOldest,
+	/// Disconnect the client immediately.
+	Disconnect,
+}
+
+/// A source of incoming connections
+pub(crate) enum ListenerConfig {
+	/// A TCP address that will be bound when the server is started
+	Bind(SocketAddr),
+	/// An already bound TCP listener
+	Tcp(TcpListener),
+	/// A path of an Unix domain socket that will be bound when the server is started
+	#[cfg(unix)]
+	BindUnix(PathBuf),
+	/// An already bound Unix domain socket listener
+	#[cfg(unix)]
+	Unix(UnixListener),
+}
+
+impl Config {
+	/// Creates a new default configuration.
+	///
+	/// If no listeners are added, the server will listen on [`DEFAULT_ADDRESS`].
+	pub fn new() -> Self {
+		Self {
+			listeners: Vec::new(),
+			shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+			proxy_protocol: None,
+			outbound_queue: OutboundQueueConfig::new(),
+			max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+			max_batch_latency: Duration::ZERO,
+			max_pending_packets: DEFAULT_MAX_PENDING_PACKETS,
+			pending_packets_timeout: DEFAULT_PENDING_PACKETS_TIMEOUT,
+			compression_level: DEFAULT_COMPRESSION_LEVEL,
+			offload_compression_size: Some(DEFAULT_OFFLOAD_COMPRESSION_SIZE),
+			strict_protocol: false,
+			callback_panic_policy: PanicPolicy::Propagate,
+		}
+	}
+	/// Adds a TCP address to listen on. Can be called multiple times to listen on multiple
+	/// addresses/ports (both IPv4 and IPv6).
+	///
+	/// The address is bound when the server is started.
+	pub fn bind(mut self, address: impl Into<SocketAddr>) -> Self {
+		self.listeners.push(ListenerConfig::Bind(address.into()));
+		self
+	}
+	/// Adds an already bound TCP listener to accept connections from.
+	pub fn listener(mut self, listener: TcpListener) -> Self {
+		self.listeners.push(ListenerConfig::Tcp(listener));
+		self
+	}
+	/// Adds an Unix domain socket to listen on. The socket is bound when the server is started.
+	///
+	/// Connections from Unix domain sockets don't have an IP address, so they will be seen as coming
+	/// from `127.0.0.1:0`.
+	#[cfg(unix)]
+	pub fn bind_unix(mut self, path: impl Into<PathBuf>) -> Self {
+		self.listeners.push(ListenerConfig::BindUnix(path.into()));
+		self
+	}
+	/// Adds an already bound Unix domain socket listener to accept connections from.
+	///
+	/// Connections from Unix domain sockets don't have an IP address, so they will be seen as coming
+	/// from `127.0.0.1:0`.
+	#[cfg(unix)]
+	pub fn unix_listener(mut self, listener: UnixListener) -> Self {
+		self.listeners.push(ListenerConfig::Unix(listener));
+		self
+	}
+	/// Sets how long to wait for all clients to receive their disconnect packets when shutting down,
+	/// before dropping their connections forcefully.
+	///
+	/// Default is [`DEFAULT_SHUTDOWN_TIMEOUT`].
+	pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+		self.shutdown_timeout = timeout;
+		self
+	}
+	/// Enables the HAProxy PROXY protocol (v1 and v2), for running behind a proxy or a load balancer.
+	///
+	/// Connections from the given trusted addresses (the proxies) must start with a PROXY protocol
+	/// header, and the client address from it will be used everywhere instead of the proxy's.
+	/// Connections from any other addresses are handled as usual, so clients can't spoof their
+	/// address.
+	pub fn proxy_protocol(mut self, trusted_sources: impl IntoIterator<Item = IpAddr>) -> Self {
+		self.proxy_protocol = Some(trusted_sources.into_iter().collect());
+		self
+	}
+	/// Sets the default outbound queue limits and overflow policy for all connections.
+	///
+	/// Can be changed for individual connections with
+	/// [`ConnectionInterface::set_outbound_queue`][crate::connection::ConnectionInterface::set_outbound_queue].
+	pub fn outbound_queue(mut self, queue: OutboundQueueConfig) -> Self {
+		self.outbound_queue = queue;
+		self
+	}
+	/// Sets the size in bytes after which a batch of packets is written to the client, even if
+	/// there are more packets queued.
+	///
+	/// All packets that are queued at the same time are written to the client at once, to reduce
+	/// the number of syscalls. Default is [`DEFAULT_MAX_BATCH_SIZE`].
+	pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+		self.max_batch_size = max_batch_size;
+		self
+	}
+	/// Sets how long to wait for more packets to add to a batch, before writing it to the client.
+	///
+	/// By default this is zero, meaning that the batch is written as soon as there are no more
+	/// queued packets. Higher values allow more packets to be written at once, at the cost of latency.
+	pub fn max_batch_latency(mut self, max_batch_latency: Duration) -> Self {
+		self.max_batch_latency = max_batch_latency;
+		self
+	}
+	/// Sets how many packets sent for a state that the client is not in yet (for example Play packets
+	/// during Configuration) can be held at once, until the client reaches that state.
+	///
+	/// If there are more, the connection is closed. Default is [`DEFAULT_MAX_PENDING_PACKETS`].
+	pub fn max_pending_packets(mut self, max_pending_packets: usize) -> Self {
+		self.max_pending_packets = max_pending_packets;
+		self
+	}
+	/// Sets how long packets sent for a state that the client is not in yet can be held.
+	///
+	/// If the client doesn't reach the state in time, the connection is closed.
+	/// Default is [`DEFAULT_PENDING_PACKETS_TIMEOUT`].
+	pub fn pending_packets_timeout(mut self, timeout: Duration) -> Self {
+		self.pending_packets_timeout = timeout;
+		self
+	}
+	/// Sets the default compression level for all connections that have compression enabled,
+	/// from 0 (no compression) to [`MAX_COMPRESSION_LEVEL`]. Higher levels are clamped to
+	/// [`MAX_COMPRESSION_LEVEL`].
+	///
+	/// Can be changed for individual connections with
+	/// [`ConnectionInterface::set_compression_level`][crate::connection::ConnectionInterface::set_compression_level].
+	/// Default is [`DEFAULT_COMPRESSION_LEVEL`].
+	pub fn compression_level(mut self, level: u32) -> Self {
+		self.compression_level = level.min(MAX_COMPRESSION_LEVEL);
+		self
+	}
+	/// Sets the size in bytes of (uncompressed) packets from which compressing and decompressing
+	/// is done on the blocking thread pool instead of the async runtime, so that big packets don't
+	/// stall other connections. The order of packets is always kept.
+	///
+	/// `None` means never. Default is [`DEFAULT_OFFLOAD_COMPRESSION_SIZE`].
+	pub fn offload_compression_size(mut self, size: Option<usize>) -> Self {
+		self.offload_compression_size = size;
+		self
+	}
+	/// Sets whether clients are disconnected when they send a packet that is not known to the
+	/// protocol crate and not handled by any [`UnknownPacket`][crate::packet_events::UnknownPacket]
+	/// callback.
+	///
+	/// Default is `false`, since not all packets are implemented yet.
+	pub fn strict_protocol(mut self, strict: bool) -> Self {
+		self.strict_protocol = strict;
+		self
+	}
+	/// Sets what happens when a callback panics, see [`PanicPolicy`].
+	///
+	/// Default is [`PanicPolicy::Propagate`], which closes the connection that triggered the event.
+	/// With [`PanicPolicy::Skip`] or [`PanicPolicy::Disable`] a buggy module doesn't disconnect players.
+	pub fn callback_panic_policy(mut self, policy: PanicPolicy) -> Self {
+		self.callback_panic_policy = policy;
+		self
+	}
+}
+
+impl OutboundQueueConfig {
+	/// Creates the default configuration: at most [`DEFAULT_MAX_QUEUED_PACKETS`] packets,
+	/// no byte limit and [`OverflowPolicy::Block`].
+	pub const fn new() -> Self {
+		Self {
+			max_packets: DEFAULT_MAX_QUEUED_PACKETS,
+			max_bytes: None,
+			overflow: OverflowPolicy::Block,
+		}
+	}
+	/// Sets the maximum number of queued packets. Must be at least 1.
+	pub fn max_packets(mut self, max_packets: usize) -> Self {
+		assert!(max_packets > 0, "max_packets must be at least 1");
+
+		self.max_packets = max_packets;
+		self
+	}
+	/// Sets the maximum total size of queued packets in bytes.
+	///
+	/// A single packet bigger than this can still be queued if the queue is empty.
+	/// Note that this requires serializing every packet one more time to know its size.
+	pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+		self.max_bytes = Some(max_bytes);
+		self
+	}
+	/// Sets what to do when the queue is full
+	pub fn on_overflow(mut self, policy: OverflowPolicy) -> Self {
+		self.overflow = policy;
+		self
+	}
+}
+
+impl Default for OutboundQueueConfig {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self::new()
+	}
+}
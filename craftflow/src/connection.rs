@@ -1,20 +1,43 @@
+mod attachments;
 mod common;
+mod compression;
 mod connection_task;
 pub mod legacy;
+mod outbound_queue;
 mod packet_reader;
 mod packet_writer;
+mod proxy_protocol;
+mod state_changes;
+mod stats;
 
-use crate::ConnId;
+use crate::{
+	ConnId,
+	config::{MAX_COMPRESSION_LEVEL, OutboundQueueConfig},
+	packet_events::RawPacket,
+	text::TextComponent,
+	various_events::DisconnectReason,
+};
 use craftflow_protocol::S2C;
 use std::{
 	fmt::Display,
 	net::IpAddr,
-	sync::{Arc, OnceLock, RwLock},
+	sync::{
+		Arc, Mutex, OnceLock, RwLock,
+		atomic::{AtomicU32, Ordering},
+	},
+	time::Duration,
 };
-use tokio::sync::mpsc::Sender;
+use tokio::time::timeout;
 use tracing::error;
 
-pub(crate) use connection_task::handle_new_conn;
+pub use attachments::Attachments;
+pub(crate) use compression::Compression;
+pub(crate) use connection_task::{can_reach, handle_new_conn};
+pub(crate) use outbound_queue::OutboundQueue;
+pub use outbound_queue::SendError;
+pub(crate) use packet_writer::EncodedPacket;
+pub(crate) use state_changes::packet_state;
+pub use stats::{ConnectionStats, PacketType};
 
 /// An interface to a client connection.
 /// Use this to send packets or end the connection (by dropping this handle).
@@ -22,14 +45,36 @@ pub struct ConnectionInterface {
 	id: ConnId,
 	ip: IpAddr,
 	protocol_version: u32,
-	packet_sender: Sender<S2C>,
+	handshake: HandshakeInfo,
+	queue: Arc<OutboundQueue>,
 
 	encryption_secret: Arc<OnceLock<[u8; 16]>>,
 	compression: Arc<OnceLock<usize>>,
+	compression_level: Arc<AtomicU32>,
 
 	// the state of the writing half of the connection.
 	// almost in all cases this will be the same as the reading half
 	writer_state: Arc<RwLock<State>>,
+
+	attachments: Attachments,
+	stats: Arc<ConnectionStats>,
+
+	// set when the server closes the connection for a known reason
+	disconnect_reason: Arc<Mutex<Option<DisconnectReason>>>,
+}
+
+/// A message to the writer task of a connection
+#[derive(Debug)]
+pub(crate) enum WriterMessage {
+	/// Send a packet, with its serialized form if it was already serialized to measure its size
+	Packet(S2C, Option<Vec<u8>>),
+	/// Send a packet that is not modelled by the protocol crate
+	Raw(RawPacket),
+	/// Send an already serialized packet, shared with other connections
+	Encoded(Arc<EncodedPacket>),
+	/// Send the appropriate disconnect packet for the current state with the given reason
+	/// and close the connection
+	Disconnect(TextComponent),
 }
 
 /// Contains all the possible states of a connection
@@ -42,13 +87,110 @@ pub enum State {
 	Play,
 }
 
+/// Why the client connected, as stated in the handshake
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Intent {
+	/// Getting the status of the server for the server list
+	Status,
+	Login,
+	/// Logging in after being transferred from another server (since 1.20.5)
+	Transfer,
+}
+
+/// The information that the client sent in the handshake, besides the protocol version
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HandshakeInfo {
+	/// The address that the client used to connect, as typed by the player.
+	///
+	/// May contain extra data appended by mod loaders or proxies, see [`ConnectionInterface::virtual_host`]
+	pub server_address: String,
+	/// The port that the client used to connect
+	pub server_port: u16,
+	pub intent: Intent,
+}
+
 impl ConnectionInterface {
 	/// Send a packet to this client.
+	///
+	/// If the outbound queue of the client is full, the [`OverflowPolicy`][crate::config::OverflowPolicy]
+	/// is applied, which may mean waiting for space.
 	pub async fn send(&self, packet: impl Into<S2C>) {
-		if self.packet_sender.send(packet.into()).await.is_err() {
-			error!("tried to send abstract packet but client writer task dead");
+		if let Err(e) = self
+			.queue
+			.push(WriterMessage::Packet(packet.into(), None), true)
+			.await
+		{
+			error!("tried to send packet to {self}: {e}");
+		}
+	}
+	/// Tries to send a packet to this client without ever waiting for space in the outbound queue.
+	///
+	/// Returns [`SendError::Full`] if the queue is full and the overflow policy is
+	/// [`Block`][crate::config::OverflowPolicy::Block].
+	pub async fn try_send(&self, packet: impl Into<S2C>) -> Result<(), SendError> {
+		self.queue
+			.push(WriterMessage::Packet(packet.into(), None), false)
+			.await
+	}
+	/// Sends a packet to this client, waiting for space in the outbound queue at most for the given
+	/// duration.
+	pub async fn send_timeout(
+		&self,
+		packet: impl Into<S2C>,
+		duration: Duration,
+	) -> Result<(), SendError> {
+		match timeout(
+			duration,
+			self.queue
+				.push(WriterMessage::Packet(packet.into(), None), true),
+		)
+		.await
+		{
+			Ok(r) => r,
+			Err(_) => Err(SendError::Timeout),
+		}
+	}
+	/// Sends a packet that is not modelled by the protocol crate, given its ID and the data
+	/// after the ID.
+	///
+	/// The packet is compressed, encrypted and ordered with other packets as usual, but no packet
+	/// events are triggered for it. If the connection is not in the given state when the packet
+	/// is written, the connection is closed, unless it is a state that will be reached later,
+	/// in which case the packet waits for it.
+	pub async fn send_raw(&self, state: State, packet_id: u32, bytes: Vec<u8>) {
+		let packet = RawPacket {
+			state,
+			id: packet_id,
+			bytes,
+		};
+		if let Err(e) = self.queue.push(WriterMessage::Raw(packet), true).await {
+			error!("tried to send raw packet to {self}: {e}");
 		}
 	}
+	/// Sets the outbound queue limits and overflow policy for this client
+	pub fn set_outbound_queue(&self, config: OutboundQueueConfig) {
+		self.queue.set_config(config);
+	}
+	/// Sends an already serialized packet to this client
+	pub(crate) async fn send_encoded(&self, packet: Arc<EncodedPacket>) {
+		if let Err(e) = self.queue.push(WriterMessage::Encoded(packet), true).await {
+			error!("tried to send encoded packet to {self}: {e}");
+		}
+	}
+	/// Sends the disconnect packet with the given reason (if there is one for the current state)
+	/// after all already queued packets, and then closes the connection.
+	pub(crate) async fn close(&self, reason: TextComponent) {
+		// if the queue is already closed, the connection is being closed anyway
+		let _ = self
+			.queue
+			.push(WriterMessage::Disconnect(reason), true)
+			.await;
+	}
+	/// Sets the reason that will be given in the [`Disconnect`][crate::various_events::Disconnect]
+	/// event, unless one was already set
+	pub(crate) fn set_disconnect_reason(&self, reason: DisconnectReason) {
+		self.disconnect_reason.lock().unwrap().get_or_insert(reason);
+	}
 	/// Set the encryption shared secret for this client.
 	/// Make sure you send and handle the appropriate packets EncryptionRequest and EncryptionResponse
 	/// this method has no safeguards.
@@ -69,6 +211,30 @@ impl ConnectionInterface {
 			error!("client compression threshold already set");
 		}
 	}
+	/// Returns the compression threshold of the client, if compression is enabled
+	pub fn compression_threshold(&self) -> Option<usize> {
+		self.compression.get().copied()
+	}
+	/// Sets the compression level for this client, from 0 (no compression) to
+	/// [`MAX_COMPRESSION_LEVEL`]. Higher levels are clamped to [`MAX_COMPRESSION_LEVEL`].
+	/// Applies to all packets sent after this.
+	///
+	/// Defaults to the level in the server [`Config`][crate::config::Config].
+	pub fn set_compression_level(&self, level: u32) {
+		self.compression_level
+			.store(level.min(MAX_COMPRESSION_LEVEL), Ordering::Relaxed);
+	}
+	/// Returns the compression level of the client
+	pub fn compression_level(&self) -> u32 {
+		self.compression_level.load(Ordering::Relaxed)
+	}
+	/// Returns how packets to this client are compressed, if compression is enabled
+	pub(crate) fn compression(&self) -> Option<Compression> {
+		self.compression.get().map(|&threshold| Compression {
+			threshold,
+			level: self.compression_level(),
+		})
+	}
 	/// Returns the protocol version of the client
 	pub fn protocol_version(&self) -> u32 {
 		self.protocol_version
@@ -81,10 +247,48 @@ impl ConnectionInterface {
 	pub fn ip(&self) -> IpAddr {
 		self.ip
 	}
+	/// Returns the host name that the client used to connect, so different content can be served
+	/// for different host names on the same port.
+	///
+	/// Any extra data that mod loaders and proxies append after a NUL character is removed, as well
+	/// as the trailing dot of a fully qualified name. See [`handshake`][Self::handshake] for the raw address.
+	pub fn virtual_host(&self) -> &str {
+		let address = self
+			.handshake
+			.server_address
+			.split('\0')
+			.next()
+			.unwrap_or("");
+		address.strip_suffix('.').unwrap_or(address)
+	}
+	/// Returns why the client connected
+	pub fn intent(&self) -> Intent {
+		self.handshake.intent
+	}
+	/// Returns the information that the client sent in the handshake
+	pub fn handshake(&self) -> &HandshakeInfo {
+		&self.handshake
+	}
 	/// Returns the ID of the connection
 	pub fn id(&self) -> ConnId {
 		self.id
 	}
+	/// Returns the typed storage for any data you want to attach to this connection.
+	/// It is dropped together with the connection.
+	pub fn attachments(&self) -> &Attachments {
+		&self.attachments
+	}
+	/// Returns the traffic statistics of this connection
+	pub fn stats(&self) -> &ConnectionStats {
+		&self.stats
+	}
+}
+
+impl Drop for ConnectionInterface {
+	fn drop(&mut self) {
+		// let the writer task know that the connection has to be closed
+		self.queue.close();
+	}
 }
 
 impl Display for ConnectionInterface {
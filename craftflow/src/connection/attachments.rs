@@ -0,0 +1,87 @@
+use std::{
+	any::{Any, TypeId},
+	collections::BTreeMap,
+	sync::{Arc, RwLock},
+};
+
+/// Typed storage of arbitrary data attached to a connection, at most one value of each type.
+///
+/// All attachments are dropped together with the connection, so there is no need to clean them up
+/// on disconnect. Since attachments are shared, use interior mutability if you need to modify them.
+pub struct Attachments {
+	inner: RwLock<BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl Attachments {
+	pub(crate) fn new() -> Self {
+		Self {
+			inner: RwLock::new(BTreeMap::new()),
+		}
+	}
+	/// Attaches a value, returning the previously attached value of the same type, if any
+	pub fn insert<T: Any + Send + Sync>(&self, value: T) -> Option<Arc<T>> {
+		self.inner
+			.write()
+			.unwrap()
+			.insert(TypeId::of::<T>(), Arc::new(value))
+			.map(|previous| previous.downcast().unwrap())
+	}
+	/// Returns the attached value of the given type, if there is one
+	pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+		self.inner
+			.read()
+			.unwrap()
+			.get(&TypeId::of::<T>())
+			.map(|value| Arc::clone(value).downcast().unwrap())
+	}
+	/// Returns the attached value of the given type, attaching one from the given function first
+	/// if there isn't one yet
+	pub fn get_or_insert_with<T: Any + Send + Sync>(&self, f: impl FnOnce() -> T) -> Arc<T> {
+		let value = Arc::clone(
+			self.inner
+				.write()
+				.unwrap()
+				.entry(TypeId::of::<T>())
+				.or_insert_with(|| Arc::new(f())),
+		);
+
+		value.downcast().unwrap()
+	}
+	/// Removes the attached value of the given type, returning it
+	pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+		self.inner
+			.write()
+			.unwrap()
+			.remove(&TypeId::of::<T>())
+			.map(|value| value.downcast().unwrap())
+	}
+	/// Checks if there is an attached value of the given type
+	pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+		self.inner.read().unwrap().contains_key(&TypeId::of::<T>())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	#[test]
+	fn test_attachments() {
+		let attachments = Attachments::new();
+
+		assert!(attachments.get::<i32>().is_none());
+		assert!(attachments.insert(42i32).is_none());
+		assert_eq!(*attachments.get::<i32>().unwrap(), 42);
+		assert_eq!(*attachments.insert(43i32).unwrap(), 42);
+
+		let counter = attachments.get_or_insert_with(|| Mutex::new(0u8));
+		*counter.lock().unwrap() += 1;
+		let counter = attachments.get_or_insert_with(|| Mutex::new(0u8));
+		assert_eq!(*counter.lock().unwrap(), 1);
+
+		assert_eq!(*attachments.remove::<i32>().unwrap(), 43);
+		assert!(!attachments.contains::<i32>());
+		assert!(attachments.contains::<Mutex<u8>>());
+	}
+}
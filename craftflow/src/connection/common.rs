@@ -6,3 +6,18 @@ pub fn varint_num_bytes(varint: i32) -> usize {
 	let bits_needed = 32 - value.leading_zeros();
 	bits_needed.div_ceil(7) as usize
 }
+
+/// Reads a VarInt from the start of the slice, returns None if it is invalid or incomplete
+pub fn read_varint(bytes: &[u8]) -> Option<i32> {
+	let mut result = 0i32;
+
+	for (i, &byte) in bytes.iter().take(5).enumerate() {
+		result |= ((byte & 0b0111_1111) as i32) << (7 * i);
+
+		if (byte & 0b1000_0000) == 0 {
+			return Some(result);
+		}
+	}
+
+	None
+}
@@ -0,0 +1,176 @@
+use std::mem;
+use tokio::task::spawn_blocking;
+
+/// How the packets of a connection are compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Compression {
+	/// Packets at least this big are compressed
+	pub(crate) threshold: usize,
+	pub(crate) level: u32,
+}
+
+/// Compresses `input[start..]` with zlib, appending to `output`.
+///
+/// If the input is at least `offload_above` bytes, the work is done on the blocking thread pool,
+/// so other connections are not stalled.
+pub(crate) async fn compress(
+	input: &mut Vec<u8>,
+	start: usize,
+	output: &mut Vec<u8>,
+	level: u32,
+	offload_above: Option<usize>,
+) -> anyhow::Result<()> {
+	if !offload_above.is_some_and(|min| input.len() - start >= min) {
+		return compress_blocking(&input[start..], output, level);
+	}
+
+	// the buffers are moved to the blocking thread and back, to avoid copying
+	let (owned_input, mut owned_output) = (mem::take(input), mem::take(output));
+	let (owned_input, owned_output, result) = spawn_blocking(move || {
+		let result = compress_blocking(&owned_input[start..], &mut owned_output, level);
+		(owned_input, owned_output, result)
+	})
+	.await?;
+	*input = owned_input;
+	*output = owned_output;
+
+	result
+}
+
+/// Decompresses zlib data, appending exactly `len` bytes to `output` or returning an error.
+///
+/// If `len` is at least `offload_above`, the work is done on the blocking thread pool,
+/// so other connections are not stalled.
+pub(crate) async fn decompress(
+	input: &[u8],
+	output: &mut Vec<u8>,
+	len: usize,
+	offload_above: Option<usize>,
+) -> anyhow::Result<()> {
+	if !offload_above.is_some_and(|min| len >= min) {
+		return decompress_blocking(input, output, len);
+	}
+
+	// the input is copied so that the caller stays cancel-safe
+	let input = input.to_vec();
+	let mut owned_output = mem::take(output);
+	let (owned_output, result) = spawn_blocking(move || {
+		let result = decompress_blocking(&input, &mut owned_output, len);
+		(owned_output, result)
+	})
+	.await?;
+	*output = owned_output;
+
+	result
+}
+
+#[cfg(not(feature = "libdeflate"))]
+fn compress_blocking(input: &[u8], output: &mut Vec<u8>, level: u32) -> anyhow::Result<()> {
+	use flate2::write::ZlibEncoder;
+	use std::io::Write;
+
+	let mut zlib = ZlibEncoder::new(output, flate2::Compression::new(level));
+	zlib.write_all(input)?;
+	zlib.finish()?;
+
+	Ok(())
+}
+
+#[cfg(not(feature = "libdeflate"))]
+fn decompress_blocking(input: &[u8], output: &mut Vec<u8>, len: usize) -> anyhow::Result<()> {
+	use flate2::write::ZlibDecoder;
+	use std::io::Write;
+
+	let start = output.len();
+	let mut zlib = ZlibDecoder::new(&mut *output);
+	zlib.write_all(input)?;
+	zlib.finish()?;
+
+	let decompressed_len = output.len() - start;
+	if decompressed_len != len {
+		anyhow::bail!(
+			"Decompressed data length mismatch: expected {}, got {}",
+			len,
+			decompressed_len
+		);
+	}
+
+	Ok(())
+}
+
+#[cfg(feature = "libdeflate")]
+fn compress_blocking(input: &[u8], output: &mut Vec<u8>, level: u32) -> anyhow::Result<()> {
+	use libdeflater::{CompressionLvl, Compressor};
+
+	let level = CompressionLvl::new(level as i32).map_err(|_| anyhow::anyhow!("invalid level"))?;
+	let mut compressor = Compressor::new(level);
+
+	let start = output.len();
+	output.resize(start + compressor.zlib_compress_bound(input.len()), 0);
+	let written = compressor.zlib_compress(input, &mut output[start..])?;
+	output.truncate(start + written);
+
+	Ok(())
+}
+
+#[cfg(feature = "libdeflate")]
+fn decompress_blocking(input: &[u8], output: &mut Vec<u8>, len: usize) -> anyhow::Result<()> {
+	use libdeflater::Decompressor;
+
+	let start = output.len();
+	output.resize(start + len, 0);
+	// fails if the data doesn't fit into exactly `len` bytes
+	let written = Decompressor::new().zlib_decompress(input, &mut output[start..])?;
+	if written != len {
+		anyhow::bail!(
+			"Decompressed data length mismatch: expected {}, got {}",
+			len,
+			written
+		);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_roundtrip() {
+		let data: Vec<u8> = (0..100_000).map(|i| (i % 13) as u8).collect();
+
+		// both inline and offloaded
+		for offload_above in [None, Some(0)] {
+			let mut input = [&[1, 2, 3][..], &data].concat();
+			let mut compressed = vec![7];
+			compress(&mut input, 3, &mut compressed, 6, offload_above)
+				.await
+				.unwrap();
+			assert_eq!(compressed[0], 7);
+			assert_eq!(&input[3..], data);
+
+			let mut decompressed = Vec::new();
+			decompress(
+				&compressed[1..],
+				&mut decompressed,
+				data.len(),
+				offload_above,
+			)
+			.await
+			.unwrap();
+			assert_eq!(decompressed, data);
+
+			assert!(
+				decompress(
+					&compressed[1..],
+					&mut Vec::new(),
+					data.len() - 1,
+					offload_above
+				)
+				.await
+				.is_err()
+			);
+		}
+	}
+}
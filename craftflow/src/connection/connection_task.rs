@@ -1,53 +1,148 @@
+mod pending_packets;
 mod reader;
 mod writer;
 
 use super::{
-	ConnectionInterface, State,
+	Attachments, Compression, ConnectionInterface, HandshakeInfo, Intent, OutboundQueue, State,
 	legacy::{LegacyPing, detect_legacy_ping, write_legacy_response},
-	packet_reader::PacketReader,
+	packet_reader::{PacketReader, ReadPacket},
 	packet_writer::PacketWriter,
+	proxy_protocol::read_proxy_header,
+	stats::ConnectionStats,
 };
 use crate::{
-	ConnId, CraftFlow, packet_events::trigger_c2s, various_events::UnsupportedClientVersion,
+	ConnId, CraftFlow,
+	packet_events::trigger_c2s,
+	text::TextComponent,
+	various_events::{
+		ConnectionHalf, DisconnectReason, EnterPlayState, Handshake, NewConnection, StateChange,
+		StateChanged, UnsupportedClientVersion,
+	},
 };
 use anyhow::{Context, bail};
 use craftflow_protocol::{
-	C2S, SUPPORTED_VERSIONS,
+	C2S, S2C, SUPPORTED_VERSIONS,
 	c2s::{Handshaking, handshaking::SetProtocol},
 	disabled_versions,
-	s2c::login::{self, disconnect::v5::DisconnectV5},
+	s2c::{
+		configuration::{
+			self,
+			disconnect::{v764::DisconnectV764, v765::DisconnectV765},
+		},
+		login::{self, disconnect::v5::DisconnectV5},
+		play::{
+			self,
+			kick_disconnect::{v5::KickDisconnectV5, v765::KickDisconnectV765},
+		},
+	},
 };
 use reader::reader_task;
 use std::{
 	net::SocketAddr,
 	ops::ControlFlow,
-	sync::{Arc, OnceLock, RwLock},
+	sync::{
+		Arc, Mutex, OnceLock, RwLock,
+		atomic::{AtomicU32, Ordering},
+	},
 	time::Duration,
 };
-use tokio::{net::TcpStream, select, spawn, sync::mpsc, time::timeout};
+use tokio::{
+	io::{AsyncRead, AsyncWrite, split},
+	select, spawn,
+	time::timeout,
+};
 use tracing::error;
 use writer::writer_task;
 
-const PACKET_CHANNEL_SIZE: usize = 16;
+pub(crate) use pending_packets::can_reach;
 
 #[derive(Clone, Debug)]
 struct ConnectionInfo {
 	id: ConnId,
 	version: u32,
 	compression: Arc<OnceLock<usize>>,
+	compression_level: Arc<AtomicU32>,
 	encryption_secret: Arc<OnceLock<[u8; 16]>>,
 	reader_state: Arc<RwLock<State>>,
 	writer_state: Arc<RwLock<State>>,
 }
 
+impl ConnectionInfo {
+	fn compression(&self) -> Option<Compression> {
+		self.compression.get().map(|&threshold| Compression {
+			threshold,
+			level: self.compression_level.load(Ordering::Relaxed),
+		})
+	}
+	/// Changes the state of one half of the connection and triggers the events
+	async fn set_state(&self, craftflow: &Arc<CraftFlow>, half: ConnectionHalf, to: State) {
+		let lock = match half {
+			ConnectionHalf::Reader => &self.reader_state,
+			ConnectionHalf::Writer => &self.writer_state,
+		};
+		let from = std::mem::replace(&mut *lock.write().unwrap(), to);
+		if from == to {
+			return;
+		}
+
+		let _ = craftflow
+			.reactor
+			.trigger::<StateChanged>(craftflow, &mut (self.id, StateChange { from, to, half }))
+			.await;
+
+		if half == ConnectionHalf::Writer && to == State::Play {
+			let _ = craftflow
+				.reactor
+				.trigger::<EnterPlayState>(craftflow, &mut { self.id })
+				.await;
+		}
+	}
+	/// Changes the state of the writing half after sending a state changing packet
+	async fn set_writer_state(&self, craftflow: &Arc<CraftFlow>, to: State) {
+		// before the acknowledgment packets the halves always switched together
+		if self.version < 764 {
+			self.set_state(craftflow, ConnectionHalf::Reader, to).await;
+		}
+		self.set_state(craftflow, ConnectionHalf::Writer, to).await;
+	}
+}
+
 /// Handles a fresh connection, managing handshake and adding to the client list
 pub(crate) async fn handle_new_conn(
 	craftflow: Arc<CraftFlow>,
-	mut stream: TcpStream,
-	socket_addr: SocketAddr,
+	mut stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	mut socket_addr: SocketAddr,
 ) -> anyhow::Result<()> {
+	// If the connection comes from a trusted proxy, get the real address of the client
+	if let Some(trusted) = &craftflow.config.proxy_protocol {
+		if trusted.contains(&socket_addr.ip()) {
+			let header = match timeout(Duration::from_secs(5), read_proxy_header(&mut stream)).await
+			{
+				Ok(r) => r.context("reading PROXY protocol header")?,
+				Err(_) => bail!("timed out trying to read PROXY protocol header"),
+			};
+
+			if let Some(client_addr) = header {
+				socket_addr = client_addr;
+			}
+		}
+	}
+
+	// Emit the new connection event
+	if craftflow
+		.reactor
+		.trigger::<NewConnection>(&craftflow, &mut socket_addr.ip())
+		.await
+		.is_break()
+	{
+		return Ok(());
+	}
+
+	// the bytes that were already read from the stream before the packet reader was created
+	let mut read_bytes = Vec::new();
+
 	// First things first check if this is a legacy ping
-	if let Some(legacy_ping_format) = detect_legacy_ping(&mut stream).await? {
+	if let Some(legacy_ping_format) = detect_legacy_ping(&mut stream, &mut read_bytes).await? {
 		// Trigger the legacy ping event
 		if let ControlFlow::Break(response) = craftflow
 			.reactor
@@ -67,10 +162,17 @@ pub(crate) async fn handle_new_conn(
 	// we will read the handshake in this task before splitting into two tasks
 	// so we know the next state for both tasks
 
-	let (reader, writer) = stream.into_split();
+	let (reader, writer) = split(stream);
 
-	let mut packet_reader = PacketReader::new(reader);
-	let mut packet_writer = PacketWriter::new(writer);
+	let offload_compression_size = craftflow.config.offload_compression_size;
+	let stats = Arc::new(ConnectionStats::new());
+	let mut packet_reader = PacketReader::new(
+		reader,
+		read_bytes,
+		offload_compression_size,
+		Arc::clone(&stats),
+	);
+	let mut packet_writer = PacketWriter::new(writer, offload_compression_size, Arc::clone(&stats));
 
 	let handshake = match timeout(
 		Duration::from_secs(5),
@@ -79,7 +181,8 @@ pub(crate) async fn handle_new_conn(
 	.await
 	{
 		Ok(r) => match r.context("reading handshake packet")? {
-			Some(p) => p,
+			Some(ReadPacket::Packet(p)) => p,
+			Some(ReadPacket::Unknown(p)) => bail!("unknown handshake packet {}", p.id),
 			None => {
 				bail!("connection closed before handshake was received");
 			}
@@ -92,13 +195,49 @@ pub(crate) async fn handle_new_conn(
 		unreachable!("there is only one packet in the handshaking state");
 	};
 
-	let next_state = match set_protocol.next_state {
-		1 => State::Status,
-		2 | 3 => State::Login, // 2 - login, 3 - transfer
+	let intent = match set_protocol.next_state {
+		1 => Intent::Status,
+		2 => Intent::Login,
+		3 => Intent::Transfer,
 		_ => bail!("invalid next_state"),
 	};
 	let version = set_protocol.protocol_version as u32;
 
+	// the connection can be refused or routed elsewhere before doing anything else
+	let mut args = (
+		socket_addr.ip(),
+		version,
+		HandshakeInfo {
+			server_address: set_protocol.server_host.clone(),
+			server_port: set_protocol.server_port,
+			intent,
+		},
+	);
+	if let ControlFlow::Break(message) = craftflow
+		.reactor
+		.trigger::<Handshake>(&craftflow, &mut args)
+		.await
+	{
+		// only clients that are logging in can be shown the message
+		let packet = match args.2.intent {
+			Intent::Status => None,
+			Intent::Login | Intent::Transfer => disconnect_packet(State::Login, version, &message),
+		};
+		if let Some(packet) = packet {
+			packet_writer
+				.send(State::Login, version, None, &mut None, &packet)
+				.await?;
+		}
+
+		return Ok(()); // close the connection
+	}
+	let (_, _, handshake) = args;
+
+	let next_state = match handshake.intent {
+		Intent::Status => State::Status,
+		Intent::Login | Intent::Transfer => State::Login,
+	};
+
 	// unless the next state is status, we need to check that the client protocol version is supported
 	if next_state != State::Status && !SUPPORTED_VERSIONS.contains(&version) {
 		let message = match craftflow
@@ -127,92 +266,172 @@ pub(crate) async fn handle_new_conn(
 	}
 
 	// All is good, can add to the client list now and spawn the tasks for reading/writing to it
-	let (packet_sender_in, packet_sender_out) = mpsc::channel(PACKET_CHANNEL_SIZE);
 	let reader_state = Arc::new(RwLock::new(next_state));
 	let writer_state = Arc::new(RwLock::new(next_state));
 	let compression = Arc::new(OnceLock::new());
+	let compression_level = Arc::new(AtomicU32::new(craftflow.config.compression_level));
 	let encryption_secret = Arc::new(OnceLock::new());
-	let id = {
+	let disconnect_reason = Arc::new(Mutex::new(None));
+	let (id, queue) = {
 		let mut lock = craftflow.connections.write().unwrap();
 
 		let id = ConnId(lock.next_conn_id);
 		lock.next_conn_id += 1;
 
+		let queue = Arc::new(OutboundQueue::new(
+			id,
+			version,
+			Arc::downgrade(&craftflow),
+			craftflow.config.outbound_queue,
+		));
+
 		lock.connections.insert(
 			id,
 			Arc::new(ConnectionInterface {
 				id,
 				ip: socket_addr.ip(),
 				protocol_version: version,
-				packet_sender: packet_sender_in,
+				handshake,
+				queue: Arc::clone(&queue),
 				encryption_secret: Arc::clone(&encryption_secret),
 				compression: Arc::clone(&compression),
+				compression_level: Arc::clone(&compression_level),
 				writer_state: Arc::clone(&writer_state),
+				attachments: Attachments::new(),
+				stats,
+				disconnect_reason: Arc::clone(&disconnect_reason),
 			}),
 		);
 
-		id
+		(id, queue)
 	};
 
 	let conn_info = ConnectionInfo {
 		id,
 		version,
 		compression,
+		compression_level,
 		encryption_secret,
 		reader_state,
 		writer_state,
 	};
 	let conn_info_clone = conn_info.clone();
 
-	let craftflow_clone = Arc::clone(&craftflow);
-	let craftflow_clone2 = Arc::clone(&craftflow);
-	let reader_task =
-		spawn(async move { reader_task(craftflow_clone, packet_reader, conn_info_clone).await });
-	let writer_task = spawn(async move {
-		writer_task(
-			craftflow_clone2,
-			packet_writer,
-			packet_sender_out,
-			conn_info,
-		)
-		.await
-	});
-
-	// now that the tasks are up and running and everything is ready
-	// just emit the handshake events for consistency with all other packets
-
+	// emit the handshake events for consistency with all other packets, and the state changes,
+	// before the tasks start, so that they come before any events of the next state
 	let handshake = set_protocol.into();
 	let (cont, handshake) = trigger_c2s(false, &craftflow, id, handshake).await;
 	if cont {
 		trigger_c2s(true, &craftflow, id, handshake).await;
 	}
+	// both halves were already switched to the next state
+	for half in [ConnectionHalf::Reader, ConnectionHalf::Writer] {
+		let change = StateChange {
+			from: State::Handshake,
+			to: next_state,
+			half,
+		};
+		let _ = craftflow
+			.reactor
+			.trigger::<StateChanged>(&craftflow, &mut (id, change))
+			.await;
+	}
+
+	let craftflow_clone = Arc::clone(&craftflow);
+	let craftflow_clone2 = Arc::clone(&craftflow);
+	let reader_task =
+		spawn(async move { reader_task(craftflow_clone, packet_reader, conn_info_clone).await });
+	let queue_clone = Arc::clone(&queue);
+	let writer_task =
+		spawn(
+			async move { writer_task(craftflow_clone2, packet_writer, queue_clone, conn_info).await },
+		);
 
 	// and now just wait for the tasks to finish for any reason and clean up
 	let reader_abort = reader_task.abort_handle();
 	let writer_abort = writer_task.abort_handle();
 
 	let result = select! {
-		r = reader_task => r.map(|inner| inner.context("reader task")).context("reader task"),
-		r = writer_task => r.map(|inner| inner.context("writer task")).context("writer task"),
+		// the reader task only ends without an error when the client closes the connection
+		r = reader_task => r.map(|inner| {
+			inner.context("reader task").map(|_| DisconnectReason::ClientClosed)
+		}),
+		// and the writer task when the server does
+		r = writer_task => r.map(|inner| {
+			inner.context("writer task").map(|_| DisconnectReason::ServerClosed)
+		}),
+		// the client didn't keep up with the packets and must be dropped
+		_ = queue.killed() => Ok(Ok(DisconnectReason::OutboundOverflow)),
 	};
 
 	// generally i dont condone abortions but in this case its fine
 	reader_abort.abort();
 	writer_abort.abort();
 
-	match result {
-		Ok(Ok(_)) => {} // ended peacefully 😊
+	let reason = match result {
+		Ok(Ok(reason)) => reason, // ended peacefully 😊
 		Ok(Err(e)) => {
 			error!("connection task error: {e:?}");
+			DisconnectReason::from_error(e)
 		}
 		Err(e) => {
 			// panicked... wow.. cringe
 			error!("connection task panicked: {e:?}");
+			DisconnectReason::ServerClosed
 		}
-	}
+	};
+
+	// the reason that was set when closing the connection takes precedence, since
+	// the tasks only see the end result
+	let reason = disconnect_reason.lock().unwrap().take().unwrap_or(reason);
 
 	// remove the connection from the list
-	craftflow.disconnect(id).await;
+	craftflow.remove_connection(id, reason).await;
 
 	Ok(())
 }
+
+/// Builds the appropriate disconnect packet with the given reason for the given state,
+/// if there is one for that state and protocol version
+fn disconnect_packet(state: State, version: u32, reason: &TextComponent) -> Option<S2C> {
+	let json = || reason.to_json();
+	// a way too long reason would not fit into NBT
+	let nbt = || {
+		reason
+			.to_nbt()
+			.unwrap_or_else(|| "Disconnected".try_into().unwrap())
+	};
+
+	let packet = match state {
+		State::Handshake | State::Status => return None,
+		State::Login if login::DisconnectBuilder::VERSIONS.contains(&version) => {
+			match login::DisconnectBuilder::new(version) {
+				login::DisconnectBuilder::V5(p) => p(DisconnectV5 { reason: json() }).into(),
+				disabled_versions!(s2c::login::DisconnectBuilder) => unreachable!(),
+			}
+		}
+		State::Configuration if configuration::DisconnectBuilder::VERSIONS.contains(&version) => {
+			match configuration::DisconnectBuilder::new(version) {
+				configuration::DisconnectBuilder::V764(p) => {
+					p(DisconnectV764 { reason: json() }).into()
+				}
+				configuration::DisconnectBuilder::V765(p) => {
+					p(DisconnectV765 { reason: nbt() }).into()
+				}
+				disabled_versions!(s2c::configuration::DisconnectBuilder) => unreachable!(),
+			}
+		}
+		State::Play if play::KickDisconnectBuilder::VERSIONS.contains(&version) => {
+			match play::KickDisconnectBuilder::new(version) {
+				play::KickDisconnectBuilder::V5(p) => p(KickDisconnectV5 { reason: json() }).into(),
+				play::KickDisconnectBuilder::V765(p) => {
+					p(KickDisconnectV765 { reason: nbt() }).into()
+				}
+				disabled_versions!(s2c::play::KickDisconnectBuilder) => unreachable!(),
+			}
+		}
+		_ => return None,
+	};
+
+	Some(packet)
+}
@@ -0,0 +1,135 @@
+use super::State;
+use anyhow::bail;
+use std::{
+	collections::{BTreeMap, VecDeque},
+	future::pending,
+	time::Duration,
+};
+use tokio::time::{Instant, sleep_until};
+
+/// Packets that were sent for a state that the writer has not reached yet,
+/// held until it does
+pub(crate) struct PendingPackets<T> {
+	max_packets: usize,
+	timeout: Duration,
+	// packets with the time they were added
+	states: BTreeMap<State, VecDeque<(Instant, T)>>,
+	len: usize,
+}
+
+impl<T> PendingPackets<T> {
+	pub(crate) fn new(max_packets: usize, timeout: Duration) -> Self {
+		Self {
+			max_packets,
+			timeout,
+			states: BTreeMap::new(),
+			len: 0,
+		}
+	}
+	/// Holds a packet until the given state is reached.
+	/// Fails if there are too many pending packets already
+	pub(crate) fn push(&mut self, state: State, packet: T) -> anyhow::Result<()> {
+		if self.len >= self.max_packets {
+			bail!(
+				"too many packets ({}) waiting for the connection to reach their state",
+				self.len
+			);
+		}
+
+		self.states
+			.entry(state)
+			.or_default()
+			.push_back((Instant::now(), packet));
+		self.len += 1;
+
+		Ok(())
+	}
+	/// Takes the next packet waiting for the given state, if there is one
+	pub(crate) fn pop(&mut self, state: State) -> Option<T> {
+		let packets = self.states.get_mut(&state)?;
+		let (_, packet) = packets.pop_front()?;
+		if packets.is_empty() {
+			self.states.remove(&state);
+		}
+		self.len -= 1;
+
+		Some(packet)
+	}
+	/// Completes with the state of the oldest packet, once it has been waiting for too long.
+	/// Never completes if there are no pending packets
+	pub(crate) async fn expired(&self) -> State {
+		match self.oldest() {
+			Some((added, state)) => {
+				sleep_until(added + self.timeout).await;
+				state
+			}
+			None => pending().await,
+		}
+	}
+	/// Returns the state of the oldest packet if it has already been waiting for too long
+	pub(crate) fn check_expired(&self) -> Option<State> {
+		self.oldest()
+			.filter(|(added, _)| added.elapsed() >= self.timeout)
+			.map(|(_, state)| state)
+	}
+	/// The number of packets waiting for each state
+	pub(crate) fn counts(&self) -> impl Iterator<Item = (State, usize)> + '_ {
+		self.states
+			.iter()
+			.map(|(&state, packets)| (state, packets.len()))
+	}
+	// the time the oldest packet was added and its state
+	fn oldest(&self) -> Option<(Instant, State)> {
+		self.states
+			.iter()
+			.filter_map(|(&state, packets)| Some((packets.front()?.0, state)))
+			.min()
+	}
+}
+
+/// Checks if the writing half of a connection can still switch from one state to another
+pub(crate) fn can_reach(from: State, to: State, protocol_version: u32) -> bool {
+	use State::*;
+
+	// the only states that can be reentered are Configuration and Play, and only from the login onwards
+	// (the Configuration state only exists since 1.20.2 (764))
+	matches!(from, Login | Configuration | Play)
+		&& (to == Play || (to == Configuration && protocol_version >= 764))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_pending_packets() {
+		let mut pending = PendingPackets::new(3, Duration::from_millis(10));
+
+		pending.push(State::Play, 1).unwrap();
+		pending.push(State::Configuration, 2).unwrap();
+		pending.push(State::Play, 3).unwrap();
+		assert!(pending.push(State::Play, 4).is_err());
+
+		assert_eq!(pending.pop(State::Play), Some(1));
+		assert_eq!(pending.pop(State::Play), Some(3));
+		assert_eq!(pending.pop(State::Play), None);
+
+		assert_eq!(pending.check_expired(), None);
+		assert_eq!(pending.expired().await, State::Configuration);
+		assert_eq!(pending.check_expired(), Some(State::Configuration));
+		assert_eq!(
+			pending.counts().collect::<Vec<_>>(),
+			[(State::Configuration, 1)]
+		);
+		assert_eq!(pending.pop(State::Configuration), Some(2));
+	}
+
+	#[test]
+	fn test_can_reach() {
+		assert!(can_reach(State::Login, State::Play, 5));
+		assert!(can_reach(State::Play, State::Configuration, 764));
+		assert!(!can_reach(State::Login, State::Configuration, 763));
+		assert!(!can_reach(State::Status, State::Play, 769));
+		assert!(!can_reach(State::Play, State::Login, 769));
+	}
+}
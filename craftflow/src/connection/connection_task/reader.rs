@@ -1,20 +1,22 @@
 use super::{ConnectionInfo, trigger_c2s};
 use crate::{
-	CraftFlow,
+	ConnId, CraftFlow,
 	connection::{
-		State,
-		packet_reader::{Decryptor, PacketReader},
+		packet_reader::{Decryptor, PacketReader, ReadPacket},
+		state_changes::reader_state_change,
 	},
+	packet_events::{RawPacket, UnknownPacket},
+	various_events::ConnectionHalf,
 };
 use aes::cipher::KeyIvInit;
 use anyhow::Context;
-use craftflow_protocol::{C2S, c2s};
-use std::sync::Arc;
+use std::{io, sync::Arc};
+use tokio::io::AsyncRead;
 use tracing::debug;
 
 pub(super) async fn reader_task(
 	craftflow: Arc<CraftFlow>,
-	mut reader: PacketReader,
+	mut reader: PacketReader<impl AsyncRead + Unpin>,
 	conn: ConnectionInfo,
 ) -> anyhow::Result<()> {
 	let mut decryptor = None;
@@ -32,40 +34,26 @@ pub(super) async fn reader_task(
 			.read_packet(state, conn.version, Some(&conn.compression), &mut decryptor)
 			.await;
 
-		// TODO when whole protocol implemented REMOVE THIS
-		// but for now dont error if unknown packet received
-		if let Err(e) = &result {
-			if let Some(e) = e.downcast_ref::<craftflow_protocol::Error>() {
-				if let craftflow_protocol::Error::UnknownPacketId {
-					id: _,
-					protocol_version: _,
-					state: _,
-				} = &e
-				{
-					debug!("received unknown packet {e}");
-					continue;
-				}
-			}
-		}
-
 		let packet = result.with_context(|| format!("reading packet (state {:?})", state))?;
 
 		// If None returned, that means the connection was cleanly closed on a packet boundary
 		// in which case we dont want to print any errors
 		let packet = match packet {
-			Some(p) => p,
+			Some(ReadPacket::Packet(p)) => p,
+			Some(ReadPacket::Unknown(packet)) => {
+				debug!(
+					"received unknown packet {} (state {:?})",
+					packet.id, packet.state
+				);
+				handle_unknown_packet(&craftflow, conn.id, packet).await?;
+				continue;
+			}
 			None => return Ok(()),
 		};
 
 		// Handle some special packets which change the state of the connection
-		match packet {
-			C2S::Login(c2s::Login::LoginAcknowledged(_)) => {
-				*conn.reader_state.write().unwrap() = State::Configuration;
-			}
-			C2S::Configuration(c2s::Configuration::FinishConfiguration(_)) => {
-				*conn.reader_state.write().unwrap() = State::Play;
-			}
-			_ => {}
+		if let Some(to) = reader_state_change(&packet) {
+			conn.set_state(&craftflow, ConnectionHalf::Reader, to).await;
 		}
 
 		let (cont, packet) = trigger_c2s(false, &craftflow, conn.id, packet).await;
@@ -74,3 +62,28 @@ pub(super) async fn reader_task(
 		}
 	}
 }
+
+// Triggers the unknown packet event, and in strict mode fails if it was not handled
+async fn handle_unknown_packet(
+	craftflow: &Arc<CraftFlow>,
+	conn_id: ConnId,
+	packet: RawPacket,
+) -> anyhow::Result<()> {
+	let (state, id) = (packet.state, packet.id);
+
+	let handled = craftflow
+		.reactor
+		.trigger::<UnknownPacket>(craftflow, &mut (conn_id, packet))
+		.await
+		.is_break();
+
+	if !handled && craftflow.config.strict_protocol {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("unknown packet {id} (state {state:?})"),
+		)
+		.into());
+	}
+
+	Ok(())
+}
@@ -1,36 +1,210 @@
-use super::ConnectionInfo;
+use super::{
+	ConnectionInfo, disconnect_packet,
+	pending_packets::{PendingPackets, can_reach},
+};
 use crate::{
 	CraftFlow,
 	connection::{
-		State,
-		packet_writer::{Encryptor, PacketWriter},
+		OutboundQueue, State, WriterMessage,
+		packet_writer::{EncodedPacket, Encryptor, PacketWriter},
+		state_changes::{packet_state, writer_state_change},
 	},
-	packet_events::trigger_s2c,
-	various_events::EnterPlayState,
+	packet_events::{RawPacket, has_s2c_callbacks, trigger_s2c},
 };
 use aes::cipher::KeyIvInit;
-use craftflow_protocol::{S2C, s2c};
-use std::sync::{Arc, OnceLock};
-use tokio::sync::mpsc::Receiver;
+use craftflow_protocol::S2C;
+use std::{
+	io,
+	ops::ControlFlow,
+	sync::{Arc, OnceLock},
+};
+use tokio::{
+	io::{AsyncWrite, AsyncWriteExt},
+	select,
+	time::{Instant, timeout_at},
+};
+use tracing::warn;
+
+/// A packet that is ready to be written, the pre-send event already triggered
+enum Outgoing {
+	Packet(S2C, Option<Vec<u8>>),
+	Raw(RawPacket),
+	Encoded(Arc<EncodedPacket>),
+}
 
 /// The task that handles writing packets to the client.
+///
+/// All packets that are queued at the same time are written in batches
 pub(super) async fn writer_task(
 	craftflow: Arc<CraftFlow>,
-	mut writer: PacketWriter,
-	mut packet_sender: Receiver<S2C>,
+	mut writer: PacketWriter<impl AsyncWrite + Unpin>,
+	queue: Arc<OutboundQueue>,
 	conn: ConnectionInfo,
 ) -> anyhow::Result<()> {
+	let max_batch_size = craftflow.config.max_batch_size;
+	let max_batch_latency = craftflow.config.max_batch_latency;
 	let mut encryptor = None;
+	let mut pending = PendingPackets::new(
+		craftflow.config.max_pending_packets,
+		craftflow.config.pending_packets_timeout,
+	);
+
+	// packets in the batch, waiting for the post-send events until it is flushed
+	let mut sent = Vec::new();
 
 	loop {
-		let packet = match packet_sender.recv().await {
-			Some(p) => p,
-			None => return Ok(()), // This means the connection has to be closed, as the handle was dropped
+		// wait for the first message of a batch
+		let message = select! {
+			message = queue.recv() => match message {
+				Some(message) => message,
+				// This means the connection has to be closed, as the handle was dropped
+				None => {
+					log_dropped(&conn, &pending);
+					return Ok(());
+				}
+			},
+			state = pending.expired() => return Err(pending_expired(&conn, &pending, state)),
 		};
+		let deadline = Instant::now() + max_batch_latency;
+		let mut message = Some(message);
+
+		// and keep adding to the batch while there are more messages
+		while let Some(m) = message.take() {
+			if handle_message(
+				&craftflow,
+				&mut writer,
+				&conn,
+				&mut encryptor,
+				&mut pending,
+				&mut sent,
+				m,
+			)
+			.await?
+			.is_break()
+			{
+				flush(&craftflow, &mut writer, &conn, &mut sent).await?;
+				log_dropped(&conn, &pending);
+				writer.stream.shutdown().await?;
+
+				return Ok(());
+			}
+
+			// the pending packets can expire while the batch is being filled too
+			if let Some(state) = pending.check_expired() {
+				return Err(pending_expired(&conn, &pending, state));
+			}
+
+			if writer.batch_len() >= max_batch_size {
+				break;
+			}
+
+			message = match queue.try_recv() {
+				Some(m) => Some(m),
+				None if max_batch_latency.is_zero() => None,
+				None => timeout_at(deadline, queue.recv()).await.ok().flatten(),
+			};
+		}
+
+		flush(&craftflow, &mut writer, &conn, &mut sent).await?;
+	}
+}
+
+// Writes the batch to the stream and triggers the post-send events of the packets in it
+async fn flush(
+	craftflow: &Arc<CraftFlow>,
+	writer: &mut PacketWriter<impl AsyncWrite + Unpin>,
+	conn: &ConnectionInfo,
+	sent: &mut Vec<S2C>,
+) -> anyhow::Result<()> {
+	writer.flush().await?;
+
+	for packet in sent.drain(..) {
+		trigger_s2c(true, craftflow, conn.id, packet).await;
+	}
+
+	Ok(())
+}
+
+// Logs the pending packets that will never be sent, since the connection is closing
+fn log_dropped(conn: &ConnectionInfo, pending: &PendingPackets<Outgoing>) {
+	for (state, count) in pending.counts() {
+		warn!(
+			"{} closed with {count} packets still waiting for the {state:?} state, dropping them",
+			conn.id
+		);
+	}
+}
+
+fn pending_expired(
+	conn: &ConnectionInfo,
+	pending: &PendingPackets<Outgoing>,
+	state: State,
+) -> anyhow::Error {
+	log_dropped(conn, pending);
+
+	io::Error::new(
+		io::ErrorKind::TimedOut,
+		format!("client didn't reach the {state:?} state in time to receive pending packets"),
+	)
+	.into()
+}
+
+// Adds the message to the batch. Returns Break if the connection has to be closed
+async fn handle_message(
+	craftflow: &Arc<CraftFlow>,
+	writer: &mut PacketWriter<impl AsyncWrite + Unpin>,
+	conn: &ConnectionInfo,
+	encryptor: &mut Option<Encryptor>,
+	pending: &mut PendingPackets<Outgoing>,
+	sent: &mut Vec<S2C>,
+	message: WriterMessage,
+) -> anyhow::Result<ControlFlow<()>> {
+	try_init_encryptor(&conn.encryption_secret, encryptor);
 
-		try_init_encryptor(&conn.encryption_secret, &mut encryptor);
-		send(&craftflow, &mut writer, &conn, &mut encryptor, packet).await?;
+	match message {
+		WriterMessage::Packet(packet, serialized) => {
+			// the already serialized packet can only be used if the events can't modify it
+			let serialized = serialized.filter(|_| !has_s2c_callbacks(craftflow, &packet));
+
+			// trigger the packet event, and actually send it if it was not cancelled
+			let (cont, packet) = trigger_s2c(false, craftflow, conn.id, packet).await;
+			if cont {
+				let packet = Outgoing::Packet(packet, serialized);
+				send(craftflow, writer, conn, encryptor, pending, sent, packet).await?;
+			}
+		}
+		// raw packets don't trigger any packet events
+		WriterMessage::Raw(packet) => {
+			let packet = Outgoing::Raw(packet);
+			send(craftflow, writer, conn, encryptor, pending, sent, packet).await?;
+		}
+		WriterMessage::Encoded(packet) => {
+			let packet = Outgoing::Encoded(packet);
+			send(craftflow, writer, conn, encryptor, pending, sent, packet).await?;
+		}
+		WriterMessage::Disconnect(reason) => {
+			// send the disconnect packet if there is one for the current state and close the connection
+			let state = *conn.writer_state.read().unwrap();
+			if let Some(packet) = disconnect_packet(state, conn.version, &reason) {
+				let (cont, packet) = trigger_s2c(false, craftflow, conn.id, packet).await;
+				if cont {
+					write(
+						craftflow,
+						writer,
+						conn,
+						encryptor,
+						sent,
+						Outgoing::Packet(packet, None),
+					)
+					.await?;
+				}
+			}
+
+			return Ok(ControlFlow::Break(()));
+		}
 	}
+
+	Ok(ControlFlow::Continue(()))
 }
 
 // Checks if the secret is set yet and initializes the encryptor if it is
@@ -42,55 +216,122 @@ fn try_init_encryptor(encryption_secret: &OnceLock<[u8; 16]>, encryptor: &mut Op
 	}
 }
 
+// Writes the packet, or holds it if it was sent for a state that the connection will reach later
 async fn send(
 	craftflow: &Arc<CraftFlow>,
-	writer: &mut PacketWriter,
+	writer: &mut PacketWriter<impl AsyncWrite + Unpin>,
 	conn: &ConnectionInfo,
 	encryptor: &mut Option<Encryptor>,
-	packet: S2C,
+	pending: &mut PendingPackets<Outgoing>,
+	sent: &mut Vec<S2C>,
+	packet: Outgoing,
 ) -> anyhow::Result<()> {
-	// trigger the packet event, and actually send it if it was not cancelled
-	let (cont, packet) = trigger_s2c(false, craftflow, conn.id, packet).await;
-	if !cont {
-		return Ok(());
+	let state = *conn.writer_state.read().unwrap();
+	let packet_state = packet.state();
+	if packet_state != state && can_reach(state, packet_state, conn.version) {
+		return pending.push(packet_state, packet);
 	}
 
-	// we check the state and compression before sending each packet individually
+	write(craftflow, writer, conn, encryptor, sent, packet).await?;
+
+	// the state might have changed, so write the packets that were waiting for it
+	loop {
+		let state = *conn.writer_state.read().unwrap();
+		let Some(packet) = pending.pop(state) else {
+			break;
+		};
+		write(craftflow, writer, conn, encryptor, sent, packet).await?;
+	}
+
+	Ok(())
+}
+
+async fn write(
+	craftflow: &Arc<CraftFlow>,
+	writer: &mut PacketWriter<impl AsyncWrite + Unpin>,
+	conn: &ConnectionInfo,
+	encryptor: &mut Option<Encryptor>,
+	sent: &mut Vec<S2C>,
+	packet: Outgoing,
+) -> anyhow::Result<()> {
+	// we check the state and compression before writing each packet individually
 	// since any of the reactor events could change them
 	let state = *conn.writer_state.read().unwrap();
-	let compression = conn.compression.get().copied();
-	writer
-		.send(state, conn.version, compression, encryptor, &packet)
-		.await?;
 
-	// some special packets that change the state of the connection
 	match packet {
-		S2C::Login(s2c::Login::Success(_)) => {
-			if conn.version >= 764 {
-				// in this version acknowledgment packets were introduced and so
-				// the states of the reader/writer separated
-				// and also Configuration state was added
-				*conn.writer_state.write().unwrap() = State::Configuration;
-			} else {
-				*conn.writer_state.write().unwrap() = State::Play;
-				*conn.reader_state.write().unwrap() = State::Play;
-				let _ = craftflow
-					.reactor
-					.trigger::<EnterPlayState>(craftflow, &mut { conn.id })
-					.await;
+		Outgoing::Packet(packet, serialized) => {
+			let compression = conn.compression();
+			match serialized {
+				Some(serialized) => {
+					writer
+						.write_serialized(
+							state,
+							conn.version,
+							compression,
+							encryptor,
+							&packet,
+							&serialized,
+						)
+						.await?
+				}
+				None => {
+					writer
+						.write(state, conn.version, compression, encryptor, &packet)
+						.await?
+				}
+			}
+
+			// some special packets that change the state of the connection
+			if let Some(to) = writer_state_change(&packet, conn.version) {
+				conn.set_writer_state(craftflow, to).await;
 			}
+
+			// the post-send event is triggered once the batch is flushed
+			sent.push(packet);
 		}
-		S2C::Configuration(s2c::Configuration::FinishConfiguration(_)) => {
-			*conn.writer_state.write().unwrap() = State::Play;
-			let _ = craftflow
-				.reactor
-				.trigger::<EnterPlayState>(craftflow, &mut { conn.id })
-				.await;
+		Outgoing::Raw(packet) => {
+			let compression = conn.compression();
+			writer
+				.write_raw(state, conn.version, compression, encryptor, &packet)
+				.await?;
 		}
-		_ => {}
-	}
+		// already serialized packets don't trigger any packet events
+		Outgoing::Encoded(packet) => {
+			// the connection can't return to the state of the packet anymore
+			if packet.state != state {
+				warn!(
+					"dropping broadcasted {:?} state packet for {}, which is in the {state:?} state",
+					packet.state, conn.id
+				);
+				return Ok(());
+			}
 
-	trigger_s2c(true, craftflow, conn.id, packet).await;
+			// the compression may have been enabled while the packet was held, then it has to be
+			// serialized again (the compression level doesn't matter to the client)
+			if packet.compression.map(|c| c.threshold) == conn.compression.get().copied() {
+				writer.write_encoded(encryptor, &packet);
+			} else {
+				let compression = conn.compression();
+				writer
+					.write(state, conn.version, compression, encryptor, &packet.packet)
+					.await?;
+			}
+
+			if let Some(to) = packet.state_change {
+				conn.set_writer_state(craftflow, to).await;
+			}
+		}
+	}
 
 	Ok(())
 }
+
+impl Outgoing {
+	fn state(&self) -> State {
+		match self {
+			Outgoing::Packet(packet, _) => packet_state(packet),
+			Outgoing::Raw(packet) => packet.state,
+			Outgoing::Encoded(packet) => packet.state,
+		}
+	}
+}
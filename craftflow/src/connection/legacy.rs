@@ -4,8 +4,7 @@ use anyhow::bail;
 use closureslop::Event;
 use std::{net::IpAddr, time::Duration};
 use tokio::{
-	io::AsyncWriteExt,
-	net::TcpStream,
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 	time::{sleep, timeout},
 };
 
@@ -29,12 +28,16 @@ impl Event for LegacyPing {
 	type Return = Option<LegacyPingResponse>;
 }
 
-/// Returns true if legacy ping detected
+/// Returns the format if legacy ping detected
+///
+/// All bytes read from the stream while detecting are appended to `buffer`, so they can be used later
+/// if it turns out to not be a legacy ping
 pub(crate) async fn detect_legacy_ping(
-	stream: &mut TcpStream,
+	stream: &mut (impl AsyncRead + Unpin),
+	buffer: &mut Vec<u8>,
 ) -> anyhow::Result<Option<LegacyPingFormat>> {
 	let mut temp_buf = [0_u8; 3];
-	let mut n = match timeout(Duration::from_secs(5), stream.peek(&mut temp_buf)).await {
+	let mut n = match timeout(Duration::from_secs(5), stream.read(&mut temp_buf)).await {
 		Ok(n) => n?,
 		Err(_) => bail!("timed out"),
 	};
@@ -63,10 +66,21 @@ pub(crate) async fn detect_legacy_ping(
 		//    that the server is old (pre-1.4).
 		//
 		// 1 is insignificant, and 2/3 are so rare that they are effectively non existant.
-		sleep(Duration::from_millis(50)).await;
-		n = stream.peek(&mut temp_buf).await?;
+		let deadline = sleep(Duration::from_millis(50));
+		tokio::pin!(deadline);
+		while n < temp_buf.len() {
+			tokio::select! {
+				r = stream.read(&mut temp_buf[n..]) => match r? {
+					0 => break,
+					read => n += read,
+				},
+				_ = &mut deadline => break,
+			}
+		}
 	}
 
+	buffer.extend_from_slice(&temp_buf[..n]);
+
 	let format = match &temp_buf[..n] {
 		[0xfe] => LegacyPingFormat::Pre1_4,
 		[0xfe, 0x01] => LegacyPingFormat::Pre1_6,
@@ -80,7 +94,7 @@ pub(crate) async fn detect_legacy_ping(
 }
 
 pub(crate) async fn write_legacy_response(
-	stream: &mut TcpStream,
+	stream: &mut (impl AsyncWrite + Unpin),
 	format: LegacyPingFormat,
 	mut response: LegacyPingResponse,
 ) -> anyhow::Result<()> {
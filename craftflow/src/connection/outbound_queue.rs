@@ -0,0 +1,415 @@
+use super::{
+	State, WriterMessage,
+	common::varint_num_bytes,
+	state_changes::{packet_state, writer_state_change},
+};
+use crate::{
+	ConnId, CraftFlow,
+	config::{OutboundQueueConfig, OverflowPolicy},
+	various_events::OutboundOverflow,
+};
+use craftflow_protocol::PacketWrite;
+use std::{
+	collections::VecDeque,
+	sync::{Mutex, Weak},
+};
+use thiserror::Error;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// The queue of messages waiting to be handled by the writer task of a connection
+pub(crate) struct OutboundQueue {
+	conn_id: ConnId,
+	protocol_version: u32,
+	craftflow: Weak<CraftFlow>,
+	inner: Mutex<Inner>,
+	// notified when a message is pushed or the queue is closed
+	pushed: Notify,
+	// notified when a message is popped or the queue is closed
+	popped: Notify,
+	// notified when the connection must be dropped immediately
+	killed: Notify,
+}
+
+struct Inner {
+	config: OutboundQueueConfig,
+	// messages with their sizes in bytes (only counted if there is a byte limit)
+	messages: VecDeque<(WriterMessage, usize)>,
+	bytes: usize,
+	// set on overflow, and reset once the queue is drained
+	overflowing: bool,
+	closed: bool,
+	killed: bool,
+}
+
+/// The outcome of trying to push a message to the queue
+enum Pushed {
+	Ok,
+	/// The queue is full and the message must wait
+	Full {
+		message: Box<WriterMessage>,
+		first_overflow: bool,
+	},
+	/// The queue overflowed and the policy was applied
+	Overflowed {
+		policy: OverflowPolicy,
+		first_overflow: bool,
+	},
+}
+
+/// The error returned when a packet could not be queued for sending
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+	/// The outbound queue is full and the overflow policy is [`OverflowPolicy::Block`]
+	#[error("outbound queue is full")]
+	Full,
+	/// Timed out waiting for space in the outbound queue
+	#[error("timed out waiting for space in the outbound queue")]
+	Timeout,
+	/// The connection is closed
+	#[error("connection closed")]
+	Closed,
+}
+
+impl OutboundQueue {
+	pub(crate) fn new(
+		conn_id: ConnId,
+		protocol_version: u32,
+		craftflow: Weak<CraftFlow>,
+		config: OutboundQueueConfig,
+	) -> Self {
+		Self {
+			conn_id,
+			protocol_version,
+			craftflow,
+			inner: Mutex::new(Inner {
+				config,
+				messages: VecDeque::new(),
+				bytes: 0,
+				overflowing: false,
+				closed: false,
+				killed: false,
+			}),
+			pushed: Notify::new(),
+			popped: Notify::new(),
+			killed: Notify::new(),
+		}
+	}
+	pub(crate) fn set_config(&self, config: OutboundQueueConfig) {
+		self.inner.lock().unwrap().config = config;
+		// blocked senders might have space now
+		self.popped.notify_waiters();
+	}
+	/// Adds a message to the queue, handling overflows according to the policy.
+	///
+	/// If `wait` is false, returns [`SendError::Full`] instead of waiting for space.
+	/// Disconnect messages are never limited, and [critical](Self::is_critical) messages are never dropped.
+	pub(crate) async fn push(
+		&self,
+		mut message: WriterMessage,
+		wait: bool,
+	) -> Result<(), SendError> {
+		let size = self.size_of(&mut message);
+
+		loop {
+			let popped = self.popped.notified();
+			tokio::pin!(popped);
+			popped.as_mut().enable();
+
+			match self.try_push(message, size)? {
+				Pushed::Ok => return Ok(()),
+				Pushed::Full {
+					message: returned,
+					first_overflow,
+				} => {
+					if first_overflow {
+						self.trigger_overflow(OverflowPolicy::Block).await;
+					}
+					if !wait {
+						return Err(SendError::Full);
+					}
+
+					popped.await;
+					message = *returned;
+				}
+				Pushed::Overflowed {
+					policy,
+					first_overflow,
+				} => {
+					if first_overflow {
+						self.trigger_overflow(policy).await;
+					}
+
+					return match policy {
+						OverflowPolicy::Disconnect => Err(SendError::Closed),
+						_ => Ok(()),
+					};
+				}
+			}
+		}
+	}
+	fn try_push(&self, message: WriterMessage, size: usize) -> Result<Pushed, SendError> {
+		let mut inner = self.inner.lock().unwrap();
+
+		if inner.closed {
+			return Err(SendError::Closed);
+		}
+
+		if matches!(message, WriterMessage::Disconnect(_)) || !inner.is_full(size) {
+			inner.push(message, size);
+			drop(inner);
+			self.pushed.notify_one();
+
+			return Ok(Pushed::Ok);
+		}
+
+		let first_overflow = !inner.overflowing;
+		inner.overflowing = true;
+
+		let policy = inner.config.overflow;
+		match policy {
+			OverflowPolicy::Block => {
+				return Ok(Pushed::Full {
+					message: Box::new(message),
+					first_overflow,
+				});
+			}
+			OverflowPolicy::DropOldest => {
+				// remove the oldest packets until there is space, but never the critical ones
+				while inner.is_full(size) {
+					let Some(i) = inner
+						.messages
+						.iter()
+						.position(|(m, _)| !self.is_critical(m))
+					else {
+						break;
+					};
+					let (_, dropped_size) = inner.messages.remove(i).unwrap();
+					inner.bytes -= dropped_size;
+				}
+				inner.push(message, size);
+				drop(inner);
+				self.pushed.notify_one();
+			}
+			OverflowPolicy::Disconnect => {
+				inner.messages.clear();
+				inner.bytes = 0;
+				inner.closed = true;
+				inner.killed = true;
+				drop(inner);
+				self.pushed.notify_one();
+				self.popped.notify_waiters();
+				self.killed.notify_one();
+
+				warn!("{} outbound queue overflowed, disconnecting", self.conn_id);
+			}
+		}
+
+		Ok(Pushed::Overflowed {
+			policy,
+			first_overflow,
+		})
+	}
+	/// Takes the next message from the queue. Returns `None` if the queue is closed and empty.
+	pub(crate) async fn recv(&self) -> Option<WriterMessage> {
+		loop {
+			if let Some(message) = self.try_recv() {
+				return Some(message);
+			}
+
+			if self.inner.lock().unwrap().closed {
+				// check again, in case something was pushed right before closing
+				return self.try_recv();
+			}
+
+			self.pushed.notified().await;
+		}
+	}
+	/// Takes the next message from the queue, if there is one
+	pub(crate) fn try_recv(&self) -> Option<WriterMessage> {
+		let mut inner = self.inner.lock().unwrap();
+
+		let (message, size) = inner.messages.pop_front()?;
+		inner.bytes -= size;
+		if inner.messages.is_empty() {
+			inner.overflowing = false;
+		}
+		drop(inner);
+		self.popped.notify_waiters();
+
+		Some(message)
+	}
+	/// Closes the queue. The already queued messages will still be handled.
+	pub(crate) fn close(&self) {
+		self.inner.lock().unwrap().closed = true;
+		self.pushed.notify_one();
+		self.popped.notify_waiters();
+	}
+	/// Completes when the connection must be dropped immediately because of an overflow
+	pub(crate) async fn killed(&self) {
+		if self.inner.lock().unwrap().killed {
+			return;
+		}
+
+		self.killed.notified().await;
+	}
+	/// Messages that must not be dropped: disconnects, login packets (encryption, compression)
+	/// and packets that change the state of the connection
+	fn is_critical(&self, message: &WriterMessage) -> bool {
+		match message {
+			WriterMessage::Packet(packet, _) => {
+				packet_state(packet) == State::Login
+					|| writer_state_change(packet, self.protocol_version).is_some()
+			}
+			WriterMessage::Raw(packet) => packet.state == State::Login,
+			WriterMessage::Encoded(packet) => {
+				packet.state == State::Login || packet.state_change.is_some()
+			}
+			WriterMessage::Disconnect(_) => true,
+		}
+	}
+	fn size_of(&self, message: &mut WriterMessage) -> usize {
+		// only bother counting the bytes if there is a limit
+		if self.inner.lock().unwrap().config.max_bytes.is_none() {
+			return 0;
+		}
+
+		match message {
+			// the writer task will reuse the serialized packet if it's not modified by the events
+			WriterMessage::Packet(packet, serialized) => {
+				let mut bytes = Vec::new();
+				let size = packet.packet_write(&mut bytes, self.protocol_version);
+				*serialized = Some(bytes);
+				size
+			}
+			WriterMessage::Raw(packet) => varint_num_bytes(packet.id as i32) + packet.bytes.len(),
+			WriterMessage::Encoded(packet) => packet.bytes.len(),
+			WriterMessage::Disconnect(_) => 0,
+		}
+	}
+	async fn trigger_overflow(&self, policy: OverflowPolicy) {
+		if let Some(craftflow) = self.craftflow.upgrade() {
+			let _ = craftflow
+				.reactor
+				.trigger::<OutboundOverflow>(&craftflow, &mut (self.conn_id, policy))
+				.await;
+		}
+	}
+}
+
+impl Inner {
+	fn is_full(&self, size: usize) -> bool {
+		if self.messages.len() >= self.config.max_packets {
+			return true;
+		}
+
+		match self.config.max_bytes {
+			// a single packet bigger than the limit is allowed if the queue is empty
+			Some(max_bytes) => !self.messages.is_empty() && self.bytes + size > max_bytes,
+			None => false,
+		}
+	}
+	fn push(&mut self, message: WriterMessage, size: usize) {
+		self.messages.push_back((message, size));
+		self.bytes += size;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use craftflow_protocol::{
+		S2C,
+		s2c::{
+			Login, Status,
+			login::{Compress, compress::v47::CompressV47},
+			status::{Ping, ping::v5::PingV5},
+		},
+	};
+
+	fn ping(time: i64) -> WriterMessage {
+		WriterMessage::Packet(S2C::Status(Status::Ping(Ping::V5(PingV5 { time }))), None)
+	}
+
+	fn queue(overflow: OverflowPolicy) -> OutboundQueue {
+		let config = OutboundQueueConfig::new()
+			.max_packets(2)
+			.on_overflow(overflow);
+
+		OutboundQueue::new(ConnId(0), 769, Weak::new(), config)
+	}
+
+	#[tokio::test]
+	async fn test_block() {
+		let queue = queue(OverflowPolicy::Block);
+
+		queue.push(ping(1), false).await.unwrap();
+		queue.push(ping(2), false).await.unwrap();
+		assert_eq!(queue.push(ping(3), false).await, Err(SendError::Full));
+
+		// the disconnect message is never limited
+		queue
+			.push(WriterMessage::Disconnect("".into()), false)
+			.await
+			.unwrap();
+
+		queue.close();
+		assert_eq!(queue.push(ping(4), true).await, Err(SendError::Closed));
+	}
+
+	#[tokio::test]
+	async fn test_drop_oldest() {
+		let queue = queue(OverflowPolicy::DropOldest);
+
+		for time in 1..=3 {
+			queue.push(ping(time), true).await.unwrap();
+		}
+		queue.close();
+
+		let mut times = Vec::new();
+		while let Some(WriterMessage::Packet(S2C::Status(p), _)) = queue.recv().await {
+			let Status::Ping(Ping::V5(p)) = p else {
+				unreachable!()
+			};
+			times.push(p.time);
+		}
+		assert_eq!(times, [2, 3]);
+	}
+
+	#[tokio::test]
+	async fn test_drop_oldest_critical() {
+		let queue = queue(OverflowPolicy::DropOldest);
+
+		let compress = S2C::Login(Login::Compress(Compress::V47(CompressV47 {
+			threshold: 256,
+		})));
+		queue
+			.push(WriterMessage::Packet(compress.clone(), None), true)
+			.await
+			.unwrap();
+		for time in 1..=3 {
+			queue.push(ping(time), true).await.unwrap();
+		}
+		queue.close();
+
+		// the login packet is never dropped
+		assert!(matches!(queue.recv().await, Some(WriterMessage::Packet(p, _)) if p == compress));
+		assert!(matches!(
+			queue.recv().await,
+			Some(WriterMessage::Packet(S2C::Status(_), _))
+		));
+		assert!(queue.recv().await.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_disconnect() {
+		let queue = queue(OverflowPolicy::Disconnect);
+
+		queue.push(ping(1), true).await.unwrap();
+		queue.push(ping(2), true).await.unwrap();
+		assert_eq!(queue.push(ping(3), true).await, Err(SendError::Closed));
+
+		queue.killed().await;
+		assert!(queue.recv().await.is_none());
+	}
+}
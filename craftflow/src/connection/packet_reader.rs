@@ -1,28 +1,49 @@
-use super::{State, common::varint_num_bytes};
-use aes::cipher::{BlockDecryptMut, generic_array::GenericArray};
+use super::{
+	State,
+	common::{read_varint, varint_num_bytes},
+	compression::decompress,
+	stats::{ConnectionStats, PacketType},
+};
+use crate::packet_events::RawPacket;
+use aes::cipher::{BlockDecryptMut, inout::InOutBuf};
 use anyhow::bail;
+use bytes::{Buf, BytesMut};
 use craftflow_protocol::{
 	C2S, PacketRead,
 	c2s::{Configuration, Handshaking, Login, Play, Status},
 };
-use flate2::write::ZlibDecoder;
-use std::{io::Write, sync::OnceLock};
+use std::sync::{Arc, OnceLock};
 use thiserror::Error;
-use tokio::{io::AsyncReadExt, net::tcp::OwnedReadHalf};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 const MAX_PACKET_SIZE: usize = 2usize.pow(21);
+const MAX_DECOMPRESSED_SIZE: usize = 2usize.pow(23);
 const DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
+// how much free space to make in the buffer before each read
+const READ_SIZE: usize = 32 * 1024;
 
 pub(crate) type Decryptor = cfb8::Decryptor<aes::Aes128>;
 
 /// Specialised BufReader than can read packets in a cancel-safe way
 /// and also handles encryption and compression
-pub(crate) struct PacketReader {
-	pub(crate) stream: OwnedReadHalf,
-	pub(crate) buffer: Vec<u8>,
+pub(crate) struct PacketReader<R> {
+	pub(crate) stream: R,
+	// received (and already decrypted) bytes that are not parsed yet
+	pub(crate) buffer: BytesMut,
 	pub(crate) decompression_buffer: Vec<u8>,
 	// If Some, this number of bytes will be removed from the buffer when starting to read a new packet
 	last_packet_len: Option<usize>,
+	// packets at least this big (decompressed) are decompressed on the blocking thread pool
+	offload_compression_size: Option<usize>,
+	stats: Arc<ConnectionStats>,
+}
+
+/// A packet received from the client
+#[derive(Debug)]
+pub(crate) enum ReadPacket {
+	Packet(C2S),
+	/// A packet with an ID that the protocol crate doesn't know
+	Unknown(RawPacket),
 }
 
 #[derive(Error, Debug)]
@@ -33,13 +54,24 @@ enum ReadVarIntError {
 	Invalid,
 }
 
-impl PacketReader {
-	pub(crate) fn new(stream: OwnedReadHalf) -> Self {
+impl<R: AsyncRead + Unpin> PacketReader<R> {
+	/// Creates a new packet reader. `buffer` may contain some bytes already read from the stream
+	pub(crate) fn new(
+		stream: R,
+		read_bytes: Vec<u8>,
+		offload_compression_size: Option<usize>,
+		stats: Arc<ConnectionStats>,
+	) -> Self {
+		let mut buffer = BytesMut::with_capacity(DEFAULT_BUFFER_SIZE.max(read_bytes.len()));
+		buffer.extend_from_slice(&read_bytes);
+
 		Self {
 			stream,
-			buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+			buffer,
 			decompression_buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
 			last_packet_len: None,
+			offload_compression_size,
+			stats,
 		}
 	}
 	/// Reads a single packet from the client (Cancel-safe)
@@ -49,10 +81,10 @@ impl PacketReader {
 		protocol_version: u32,
 		compression: Option<&OnceLock<usize>>,
 		decryptor: &mut Option<Decryptor>,
-	) -> anyhow::Result<Option<C2S>> {
+	) -> anyhow::Result<Option<ReadPacket>> {
 		if let Some(last_packet_len) = self.last_packet_len.take() {
 			// remove the packet bytes from the buffer
-			self.buffer.drain(..last_packet_len);
+			self.buffer.advance(last_packet_len);
 		}
 
 		// wait for the length of the next packet
@@ -91,7 +123,11 @@ impl PacketReader {
 				let length = self.read_varint_at_pos(packet_start, decryptor).await?;
 				packet_start += varint_num_bytes(length);
 
-				if length >= threshold as i32 {
+				if length as usize > MAX_DECOMPRESSED_SIZE {
+					bail!(
+						"decompressed packet len must be less than {MAX_DECOMPRESSED_SIZE} bytes (got {length} bytes)"
+					);
+				} else if length >= threshold as i32 {
 					Some(length as usize)
 				} else if length == 0 {
 					None
@@ -116,43 +152,44 @@ impl PacketReader {
 		if let Some(decompressed_len) = decompressed_len {
 			// decompress the packet bytes and make sure the length is correct
 			self.decompression_buffer.clear();
-			let mut writer = ZlibDecoder::new(&mut self.decompression_buffer);
-			writer.write_all(packet_bytes)?;
-			writer.finish()?;
-
-			if self.decompression_buffer.len() != decompressed_len {
-				bail!(
-					"Decompressed data length mismatch: expected {}, got {}",
-					decompressed_len,
-					self.decompression_buffer.len()
-				);
-			}
+			decompress(
+				packet_bytes,
+				&mut self.decompression_buffer,
+				decompressed_len,
+				self.offload_compression_size,
+			)
+			.await?;
 
 			packet_bytes = &self.decompression_buffer[..];
 		}
 
 		// Parse the packet
-		let packet = match state {
+		let bytes = packet_bytes;
+		let result = match state {
 			State::Handshake => {
-				let packet = Handshaking::packet_read(&mut packet_bytes, protocol_version)?;
-				packet.into()
+				Handshaking::packet_read(&mut packet_bytes, protocol_version).map(C2S::from)
 			}
 			State::Status => {
-				let packet = Status::packet_read(&mut packet_bytes, protocol_version)?;
-				packet.into()
-			}
-			State::Login => {
-				let packet = Login::packet_read(&mut packet_bytes, protocol_version)?;
-				packet.into()
+				Status::packet_read(&mut packet_bytes, protocol_version).map(C2S::from)
 			}
+			State::Login => Login::packet_read(&mut packet_bytes, protocol_version).map(C2S::from),
 			State::Configuration => {
-				let packet = Configuration::packet_read(&mut packet_bytes, protocol_version)?;
-				packet.into()
+				Configuration::packet_read(&mut packet_bytes, protocol_version).map(C2S::from)
 			}
-			State::Play => {
-				let packet = Play::packet_read(&mut packet_bytes, protocol_version)?;
-				packet.into()
+			State::Play => Play::packet_read(&mut packet_bytes, protocol_version).map(C2S::from),
+		};
+		let packet = match result {
+			Ok(packet) => packet,
+			// not all packets are implemented, so these are given raw
+			Err(craftflow_protocol::Error::UnknownPacketId { id, .. }) => {
+				self.stats
+					.record_received(PacketType { state, id }, total_packet_len, bytes.len());
+				let bytes = bytes[varint_num_bytes(id as i32)..].to_vec();
+				self.last_packet_len = Some(total_packet_len);
+
+				return Ok(Some(ReadPacket::Unknown(RawPacket { state, id, bytes })));
 			}
+			Err(e) => Err(e)?,
 		};
 
 		// simple sanity test of parsing the packet, all the bytes should have been used to parse it
@@ -163,9 +200,13 @@ impl PacketReader {
 			);
 		}
 
+		// the packet was parsed, so the ID is valid
+		let id = read_varint(bytes).unwrap() as u32;
+		self.stats
+			.record_received(PacketType { state, id }, total_packet_len, bytes.len());
 		self.last_packet_len = Some(total_packet_len);
 
-		Ok(Some(packet))
+		Ok(Some(ReadPacket::Packet(packet)))
 	}
 	/// Reads a VarInt in a cancel safe way at a specific position in the buffer
 	/// without removing the bytes from the buffer
@@ -201,12 +242,14 @@ impl PacketReader {
 
 		Ok(result)
 	}
-	/// Reads more data into the buffer
+	/// Reads more data straight into the buffer, decrypting it in place if needed
 	/// returns how many bytes were read
 	async fn read(&mut self, decryptor: &mut Option<Decryptor>) -> std::io::Result<usize> {
-		let mut temp = [0u8; 32 * 1024];
+		// reclaims the space of already parsed packets if possible, so this rarely allocates
+		self.buffer.reserve(READ_SIZE);
 
-		let n = self.stream.read(&mut temp[..]).await?;
+		let start = self.buffer.len();
+		let n = self.stream.read_buf(&mut self.buffer).await?;
 
 		if n == 0 {
 			return Err(std::io::ErrorKind::UnexpectedEof.into());
@@ -214,13 +257,10 @@ impl PacketReader {
 
 		// Instantly decrypt the bytes we just read if encryption is enabled
 		if let Some(decryptor) = decryptor {
-			for i in 0..n {
-				decryptor.decrypt_block_mut(GenericArray::from_mut_slice(&mut temp[i..(i + 1)]));
-			}
+			let (blocks, _) = InOutBuf::from(&mut self.buffer[start..]).into_chunks();
+			decryptor.decrypt_blocks_inout_mut(blocks);
 		}
 
-		self.buffer.extend_from_slice(&temp[..n]);
-
 		Ok(n)
 	}
 }
@@ -1,56 +1,113 @@
-use super::{State, common::varint_num_bytes};
-use aes::cipher::{BlockEncryptMut, generic_array::GenericArray};
+use super::{
+	State,
+	common::{read_varint, varint_num_bytes},
+	compression::{Compression, compress},
+	state_changes::{packet_state, writer_state_change},
+	stats::{ConnectionStats, PacketType},
+};
+use crate::packet_events::RawPacket;
+use aes::cipher::{BlockEncryptMut, inout::InOutBuf};
 use anyhow::bail;
 use craftflow_protocol::{PacketWrite, S2C};
-use flate2::write::ZlibEncoder;
-use std::io::Write;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-// 0 is no compression, 9 - take as long as you'd like
-const COMPRESSION_LEVEL: u32 = 6;
 const DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
 
 pub(crate) type Encryptor = cfb8::Encryptor<aes::Aes128>;
 
+/// A packet that is already serialized (but not encrypted), so the same bytes can be sent to many clients
+#[derive(Debug)]
+pub(crate) struct EncodedPacket {
+	/// The state that the packet belongs to
+	pub(crate) state: State,
+	/// The state that the connection switches to after sending this packet, if any
+	pub(crate) state_change: Option<State>,
+	/// The compression that was used when serializing
+	pub(crate) compression: Option<Compression>,
+	pub(crate) id: u32,
+	/// The length of the packet ID and data before compression
+	pub(crate) uncompressed_len: usize,
+	pub(crate) bytes: Vec<u8>,
+	/// The packet itself, in case it has to be serialized again
+	pub(crate) packet: S2C,
+}
+
+/// A serialized packet in one of the buffers
+struct Encoded<'a> {
+	id: u32,
+	uncompressed_len: usize,
+	bytes: &'a mut [u8],
+}
+
 /// Keeps track of the current state of the connection and allows to write packets easily
-pub(crate) struct PacketWriter {
-	pub(crate) stream: OwnedWriteHalf,
+///
+/// Packets are collected in a batch and only written to the stream when flushed
+pub(crate) struct PacketWriter<W> {
+	pub(crate) stream: W,
 	pub(crate) buffer: Vec<u8>,
 	pub(crate) compression_buffer: Vec<u8>,
+	// final (compressed, encrypted) bytes of packets waiting to be flushed
+	pub(crate) batch: Vec<u8>,
+	// packets at least this big are compressed on the blocking thread pool
+	pub(crate) offload_compression_size: Option<usize>,
+	pub(crate) stats: Arc<ConnectionStats>,
 }
 
-impl PacketWriter {
-	pub(crate) fn new(stream: OwnedWriteHalf) -> Self {
+impl<W: AsyncWrite + Unpin> PacketWriter<W> {
+	pub(crate) fn new(
+		stream: W,
+		offload_compression_size: Option<usize>,
+		stats: Arc<ConnectionStats>,
+	) -> Self {
 		Self {
 			stream,
 			buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
 			compression_buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+			batch: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+			offload_compression_size,
+			stats,
 		}
 	}
-	/// Sends a packet to the client, automatically checking if the packet is valid for the current state
+	/// Sends a packet to the client immediately, automatically checking if the packet is valid for the current state
 	pub(crate) async fn send(
 		&mut self,
 		state: State,
 		protocol_version: u32,
-		compression: Option<usize>,
+		compression: Option<Compression>,
+		encryptor: &mut Option<Encryptor>,
+		packet: &S2C,
+	) -> anyhow::Result<()> {
+		self.write(state, protocol_version, compression, encryptor, packet)
+			.await?;
+		self.flush().await?;
+
+		Ok(())
+	}
+	/// Adds a packet to the batch, automatically checking if the packet is valid for the current state
+	pub(crate) async fn write(
+		&mut self,
+		state: State,
+		protocol_version: u32,
+		compression: Option<Compression>,
 		encryptor: &mut Option<Encryptor>,
 		packet: &S2C,
 	) -> anyhow::Result<()> {
 		match packet {
 			S2C::Status(p) if state == State::Status => {
-				self.write_unchecked(protocol_version, compression, encryptor, p)
+				self.write_unchecked(state, protocol_version, compression, encryptor, p)
 					.await?;
 			}
 			S2C::Login(p) if state == State::Login => {
-				self.write_unchecked(protocol_version, compression, encryptor, p)
+				self.write_unchecked(state, protocol_version, compression, encryptor, p)
 					.await?;
 			}
 			S2C::Configuration(p) if state == State::Configuration => {
-				self.write_unchecked(protocol_version, compression, encryptor, p)
+				self.write_unchecked(state, protocol_version, compression, encryptor, p)
 					.await?;
 			}
 			S2C::Play(p) if state == State::Play => {
-				self.write_unchecked(protocol_version, compression, encryptor, p)
+				self.write_unchecked(state, protocol_version, compression, encryptor, p)
 					.await?;
 			}
 			_ => {
@@ -64,72 +121,247 @@ impl PacketWriter {
 
 		Ok(())
 	}
+	/// Adds a packet that was already serialized by the protocol crate to the batch,
+	/// checking if the packet is valid for the current state
+	pub(crate) async fn write_serialized(
+		&mut self,
+		state: State,
+		protocol_version: u32,
+		compression: Option<Compression>,
+		encryptor: &mut Option<Encryptor>,
+		packet: &S2C,
+		serialized: &[u8],
+	) -> anyhow::Result<()> {
+		if packet_state(packet) != state {
+			bail!(
+				"Attempt to send packet on wrong state.\nState: {:?}\nPacket: {:?}",
+				state,
+				packet
+			);
+		}
+
+		self.write_unchecked(
+			state,
+			protocol_version,
+			compression,
+			encryptor,
+			&Serialized(serialized),
+		)
+		.await
+	}
+	/// Adds a raw packet to the batch, checking if it was made for the current state
+	pub(crate) async fn write_raw(
+		&mut self,
+		state: State,
+		protocol_version: u32,
+		compression: Option<Compression>,
+		encryptor: &mut Option<Encryptor>,
+		packet: &RawPacket,
+	) -> anyhow::Result<()> {
+		if packet.state != state {
+			bail!(
+				"Attempt to send raw packet on wrong state.\nState: {:?}\nPacket state: {:?}",
+				state,
+				packet.state
+			);
+		}
 
-	/// Writes anything writable as a packet into the stream
+		self.write_unchecked(state, protocol_version, compression, encryptor, packet)
+			.await
+	}
+
+	/// Adds anything writable as a packet to the batch
 	/// Doesnt check if the packet is valid for the current state
 	async fn write_unchecked(
 		&mut self,
+		state: State,
 		protocol_version: u32,
-		compression: Option<usize>,
+		compression: Option<Compression>,
 		encryptor: &mut Option<Encryptor>,
 		packet: &impl PacketWrite,
 	) -> anyhow::Result<()> {
-		self.buffer.clear();
-		self.compression_buffer.clear();
-
-		let mut buffer = &mut self.buffer;
-
-		// leave space at the start of the buffer for two potential varints
-		// (length and uncompressed length)
-		const MAX_PREFIX: usize = 5 * 2; // 2 varints
-		buffer.extend([0u8; MAX_PREFIX]);
-		let mut packet_start = MAX_PREFIX;
-
-		// Write the packet to the buffer
-		let uncompressed_len = packet.packet_write(buffer, protocol_version);
-
-		// compress the packet if compression is enabled
-		'compression: {
-			if let Some(threshold) = compression {
-				if uncompressed_len < threshold {
-					// since compression is enabled but we're not compressing
-					// set the uncompressed length to 0
-					prepend_to_buffer(buffer, &mut packet_start, 0);
-
-					break 'compression;
-				}
-
-				self.compression_buffer.resize(packet_start, 0);
-				let mut zlib = ZlibEncoder::new(
-					&mut self.compression_buffer,
-					flate2::Compression::new(COMPRESSION_LEVEL),
-				);
-				zlib.write_all(&buffer[packet_start..])?;
-				zlib.finish()?;
+		let encoded = encode_packet(
+			&mut self.buffer,
+			&mut self.compression_buffer,
+			protocol_version,
+			compression,
+			self.offload_compression_size,
+			packet,
+		)
+		.await?;
 
-				buffer = &mut self.compression_buffer;
+		let packet_type = PacketType {
+			state,
+			id: encoded.id,
+		};
+		self.stats
+			.record_sent(packet_type, encoded.bytes.len(), encoded.uncompressed_len);
 
-				// write the uncompressed packet length
-				prepend_to_buffer(buffer, &mut packet_start, uncompressed_len as i32);
-			}
+		// encrypt the packet if encryption is enabled
+		encrypt(encryptor, encoded.bytes);
+
+		self.batch.extend_from_slice(encoded.bytes);
+
+		Ok(())
+	}
+	/// Adds an already encoded packet to the batch, encrypting it if needed
+	/// Doesnt check if the packet is valid for the current state
+	pub(crate) fn write_encoded(
+		&mut self,
+		encryptor: &mut Option<Encryptor>,
+		packet: &EncodedPacket,
+	) {
+		let packet_type = PacketType {
+			state: packet.state,
+			id: packet.id,
+		};
+		self.stats
+			.record_sent(packet_type, packet.bytes.len(), packet.uncompressed_len);
+
+		let start = self.batch.len();
+		self.batch.extend_from_slice(&packet.bytes);
+		encrypt(encryptor, &mut self.batch[start..]);
+	}
+	/// The number of bytes waiting to be flushed
+	pub(crate) fn batch_len(&self) -> usize {
+		self.batch.len()
+	}
+	/// Writes all batched packets to the stream
+	pub(crate) async fn flush(&mut self) -> std::io::Result<()> {
+		if self.batch.is_empty() {
+			return Ok(());
 		}
 
-		// write the total packet length
-		let total_packet_len = buffer.len() - packet_start;
-		prepend_to_buffer(buffer, &mut packet_start, total_packet_len as i32);
+		self.stream.write_all(&self.batch).await?;
+		self.batch.clear();
 
-		// encrypt the packet if encryption is enabled
-		let bytes = &mut buffer[packet_start..];
-		if let Some(encryptor) = encryptor {
-			for i in 0..bytes.len() {
-				encryptor.encrypt_block_mut(GenericArray::from_mut_slice(&mut bytes[i..(i + 1)]));
+		Ok(())
+	}
+}
+
+impl EncodedPacket {
+	/// Serializes a packet, so it can be sent to many connections with the same protocol version
+	/// and compression
+	pub(crate) async fn new(
+		protocol_version: u32,
+		compression: Option<Compression>,
+		offload_compression_size: Option<usize>,
+		packet: S2C,
+	) -> anyhow::Result<Self> {
+		let (mut buffer, mut compression_buffer) = (Vec::new(), Vec::new());
+		let encoded = encode_packet(
+			&mut buffer,
+			&mut compression_buffer,
+			protocol_version,
+			compression,
+			offload_compression_size,
+			&packet,
+		)
+		.await?;
+
+		Ok(Self {
+			state: packet_state(&packet),
+			state_change: writer_state_change(&packet, protocol_version),
+			compression,
+			id: encoded.id,
+			uncompressed_len: encoded.uncompressed_len,
+			bytes: encoded.bytes.to_vec(),
+			packet,
+		})
+	}
+}
+
+impl PacketWrite for RawPacket {
+	fn packet_write(&self, output: &mut Vec<u8>, _protocol_version: u32) -> usize {
+		let start = output.len();
+
+		let id = self.id as i32;
+		output.resize(start + varint_num_bytes(id), 0);
+		write_varint(id, &mut output[start..]);
+		output.extend_from_slice(&self.bytes);
+
+		output.len() - start
+	}
+}
+
+/// The ID and data of a packet, already serialized
+struct Serialized<'a>(&'a [u8]);
+
+impl PacketWrite for Serialized<'_> {
+	fn packet_write(&self, output: &mut Vec<u8>, _protocol_version: u32) -> usize {
+		output.extend_from_slice(self.0);
+
+		self.0.len()
+	}
+}
+
+/// Serializes a packet with the length prefix, compressing if needed.
+/// The final packet bytes are in one of the buffers
+async fn encode_packet<'a>(
+	mut buffer: &'a mut Vec<u8>,
+	compression_buffer: &'a mut Vec<u8>,
+	protocol_version: u32,
+	compression: Option<Compression>,
+	offload_compression_size: Option<usize>,
+	packet: &impl PacketWrite,
+) -> anyhow::Result<Encoded<'a>> {
+	buffer.clear();
+	compression_buffer.clear();
+
+	// leave space at the start of the buffer for two potential varints
+	// (length and uncompressed length)
+	const MAX_PREFIX: usize = 5 * 2; // 2 varints
+	buffer.extend([0u8; MAX_PREFIX]);
+	let mut packet_start = MAX_PREFIX;
+
+	// Write the packet to the buffer
+	let uncompressed_len = packet.packet_write(buffer, protocol_version);
+	let id = read_varint(&buffer[packet_start..]).expect("packets start with the ID") as u32;
+
+	// compress the packet if compression is enabled
+	'compression: {
+		if let Some(compression) = compression {
+			if uncompressed_len < compression.threshold {
+				// since compression is enabled but we're not compressing
+				// set the uncompressed length to 0
+				prepend_to_buffer(buffer, &mut packet_start, 0);
+
+				break 'compression;
 			}
+
+			compression_buffer.resize(packet_start, 0);
+			compress(
+				buffer,
+				packet_start,
+				compression_buffer,
+				compression.level,
+				offload_compression_size,
+			)
+			.await?;
+
+			buffer = compression_buffer;
+
+			// write the uncompressed packet length
+			prepend_to_buffer(buffer, &mut packet_start, uncompressed_len as i32);
 		}
+	}
 
-		// write the packet to the stream
-		self.stream.write_all(bytes).await?;
+	// write the total packet length
+	let total_packet_len = buffer.len() - packet_start;
+	prepend_to_buffer(buffer, &mut packet_start, total_packet_len as i32);
 
-		Ok(())
+	Ok(Encoded {
+		id,
+		uncompressed_len,
+		bytes: &mut buffer[packet_start..],
+	})
+}
+
+fn encrypt(encryptor: &mut Option<Encryptor>, bytes: &mut [u8]) {
+	if let Some(encryptor) = encryptor {
+		// CFB8 has 1 byte blocks, so there is never a remainder
+		let (blocks, _) = InOutBuf::from(bytes).into_chunks();
+		encryptor.encrypt_blocks_inout_mut(blocks);
 	}
 }
 
@@ -0,0 +1,115 @@
+//! Parsing of the HAProxy PROXY protocol header (v1 and v2)
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>
+
+use anyhow::{Context, bail};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// the maximum length of a v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads the PROXY protocol header from the start of the stream, without reading anything more.
+///
+/// Returns the address of the real client, or `None` if the proxy said that the connection
+/// is not proxied (for example a health check) and the address of the peer should be used.
+pub(crate) async fn read_proxy_header(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<Option<SocketAddr>> {
+	// both versions are at least this long
+	let mut start = [0u8; 12];
+	stream.read_exact(&mut start).await?;
+
+	if &start == V2_SIGNATURE {
+		read_v2(stream).await
+	} else if start.starts_with(V1_PREFIX) {
+		read_v1(stream, start).await
+	} else {
+		bail!("expected a PROXY protocol header");
+	}
+}
+
+async fn read_v1(
+	stream: &mut (impl AsyncRead + Unpin),
+	start: [u8; 12],
+) -> anyhow::Result<Option<SocketAddr>> {
+	let mut line = start.to_vec();
+
+	// read byte by byte, so nothing after the header is consumed
+	while !line.ends_with(b"\r\n") {
+		if line.len() >= V1_MAX_LEN {
+			bail!("PROXY protocol v1 header too long");
+		}
+		line.push(stream.read_u8().await?);
+	}
+
+	let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+		.context("PROXY protocol v1 header not valid UTF-8")?;
+	let mut parts = line.split(' ');
+
+	match parts.next() {
+		Some("TCP4" | "TCP6") => {}
+		Some("UNKNOWN") => return Ok(None),
+		_ => bail!("invalid PROXY protocol v1 header"),
+	}
+
+	let (Some(source_ip), Some(_dest_ip), Some(source_port), Some(_dest_port), None) = (
+		parts.next(),
+		parts.next(),
+		parts.next(),
+		parts.next(),
+		parts.next(),
+	) else {
+		bail!("invalid PROXY protocol v1 header");
+	};
+
+	let ip: IpAddr = source_ip
+		.parse()
+		.context("invalid source address in PROXY protocol v1 header")?;
+	let port: u16 = source_port
+		.parse()
+		.context("invalid source port in PROXY protocol v1 header")?;
+
+	Ok(Some(SocketAddr::new(ip, port)))
+}
+
+async fn read_v2(stream: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Option<SocketAddr>> {
+	let version_command = stream.read_u8().await?;
+	let family = stream.read_u8().await?;
+	let len = stream.read_u16().await? as usize;
+
+	// always read the whole header, even if we dont need it
+	let mut data = vec![0; len];
+	stream.read_exact(&mut data).await?;
+
+	if version_command >> 4 != 2 {
+		bail!("unsupported PROXY protocol version");
+	}
+	match version_command & 0x0f {
+		0 => return Ok(None), // LOCAL
+		1 => {}               // PROXY
+		_ => bail!("invalid PROXY protocol v2 command"),
+	}
+
+	// the high nibble is the address family, the low one - transport protocol
+	let address = match family >> 4 {
+		// IPv4
+		1 if len >= 12 => {
+			let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&data[0..4]).unwrap());
+			let port = u16::from_be_bytes([data[8], data[9]]);
+			SocketAddr::new(ip.into(), port)
+		}
+		// IPv6
+		2 if len >= 36 => {
+			let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&data[0..16]).unwrap());
+			let port = u16::from_be_bytes([data[32], data[33]]);
+			SocketAddr::new(ip.into(), port)
+		}
+		1 | 2 => bail!("PROXY protocol v2 address block too short"),
+		// unspecified or unix sockets, no useful address
+		_ => return Ok(None),
+	};
+
+	Ok(Some(address))
+}
@@ -0,0 +1,45 @@
+//! The packets that change the state of a connection
+
+use super::State;
+use craftflow_protocol::{C2S, S2C, c2s, s2c};
+
+/// Returns the state that a packet can be sent in
+pub(crate) fn packet_state(packet: &S2C) -> State {
+	match packet {
+		S2C::Status(_) => State::Status,
+		S2C::Login(_) => State::Login,
+		S2C::Configuration(_) => State::Configuration,
+		S2C::Play(_) => State::Play,
+	}
+}
+
+/// Returns the state that the writing half of the connection switches to after sending this packet
+///
+/// Before 1.20.2 (764) the halves were not separate, so the reading half switches too.
+pub(crate) fn writer_state_change(packet: &S2C, protocol_version: u32) -> Option<State> {
+	match packet {
+		S2C::Login(s2c::Login::Success(_)) => {
+			if protocol_version >= 764 {
+				// in this version acknowledgment packets were introduced and so
+				// the states of the reader/writer separated
+				// and also Configuration state was added
+				Some(State::Configuration)
+			} else {
+				Some(State::Play)
+			}
+		}
+		S2C::Configuration(s2c::Configuration::FinishConfiguration(_)) => Some(State::Play),
+		S2C::Play(s2c::Play::StartConfiguration(_)) => Some(State::Configuration),
+		_ => None,
+	}
+}
+
+/// Returns the state that the reading half of the connection switches to after receiving this packet
+pub(crate) fn reader_state_change(packet: &C2S) -> Option<State> {
+	match packet {
+		C2S::Login(c2s::Login::LoginAcknowledged(_)) => Some(State::Configuration),
+		C2S::Configuration(c2s::Configuration::FinishConfiguration(_)) => Some(State::Play),
+		C2S::Play(c2s::Play::ConfigurationAcknowledged(_)) => Some(State::Configuration),
+		_ => None,
+	}
+}
@@ -0,0 +1,135 @@
+use super::State;
+use std::{
+	collections::BTreeMap,
+	sync::{
+		Mutex,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+/// Traffic statistics of a connection, updated as packets are read and written.
+///
+/// The plain byte counts are what actually went over the network, including the length prefixes.
+/// The (de)compressed counts only include the packet IDs and data, before compression.
+pub struct ConnectionStats {
+	started: Instant,
+	received: Direction,
+	sent: Direction,
+}
+
+/// A type of packet, as identified on the wire. The same ID may mean different packets
+/// in different protocol versions.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct PacketType {
+	pub state: State,
+	pub id: u32,
+}
+
+struct Direction {
+	bytes: AtomicU64,
+	bytes_uncompressed: AtomicU64,
+	packets: AtomicU64,
+	// time of the last packet since the start of the connection, in nanoseconds
+	last_packet: AtomicU64,
+	packet_types: Mutex<BTreeMap<PacketType, u64>>,
+}
+
+impl ConnectionStats {
+	pub(crate) fn new() -> Self {
+		Self {
+			started: Instant::now(),
+			received: Direction::new(),
+			sent: Direction::new(),
+		}
+	}
+	/// The time when the connection was opened
+	pub fn started(&self) -> Instant {
+		self.started
+	}
+	/// The number of bytes received from the client
+	pub fn bytes_received(&self) -> u64 {
+		self.received.bytes.load(Ordering::Relaxed)
+	}
+	/// The number of bytes of packet IDs and data received from the client, after decompression
+	pub fn bytes_received_decompressed(&self) -> u64 {
+		self.received.bytes_uncompressed.load(Ordering::Relaxed)
+	}
+	/// The number of bytes sent to the client
+	pub fn bytes_sent(&self) -> u64 {
+		self.sent.bytes.load(Ordering::Relaxed)
+	}
+	/// The number of bytes of packet IDs and data sent to the client, before compression
+	pub fn bytes_sent_uncompressed(&self) -> u64 {
+		self.sent.bytes_uncompressed.load(Ordering::Relaxed)
+	}
+	/// The number of packets received from the client
+	pub fn packets_received(&self) -> u64 {
+		self.received.packets.load(Ordering::Relaxed)
+	}
+	/// The number of packets sent to the client
+	pub fn packets_sent(&self) -> u64 {
+		self.sent.packets.load(Ordering::Relaxed)
+	}
+	/// The number of packets of each type received from the client
+	pub fn packet_types_received(&self) -> BTreeMap<PacketType, u64> {
+		self.received.packet_types.lock().unwrap().clone()
+	}
+	/// The number of packets of each type sent to the client
+	pub fn packet_types_sent(&self) -> BTreeMap<PacketType, u64> {
+		self.sent.packet_types.lock().unwrap().clone()
+	}
+	/// The time when the last packet was received, or when the connection was opened
+	/// if there were none yet
+	pub fn last_received(&self) -> Instant {
+		self.started + self.received.last_packet()
+	}
+	/// The time when the last packet was sent, or when the connection was opened
+	/// if there were none yet
+	pub fn last_sent(&self) -> Instant {
+		self.started + self.sent.last_packet()
+	}
+	pub(crate) fn record_received(
+		&self,
+		packet_type: PacketType,
+		bytes: usize,
+		decompressed: usize,
+	) {
+		self.received
+			.record(self.started, packet_type, bytes, decompressed);
+	}
+	pub(crate) fn record_sent(&self, packet_type: PacketType, bytes: usize, uncompressed: usize) {
+		self.sent
+			.record(self.started, packet_type, bytes, uncompressed);
+	}
+}
+
+impl Direction {
+	fn new() -> Self {
+		Self {
+			bytes: AtomicU64::new(0),
+			bytes_uncompressed: AtomicU64::new(0),
+			packets: AtomicU64::new(0),
+			last_packet: AtomicU64::new(0),
+			packet_types: Mutex::new(BTreeMap::new()),
+		}
+	}
+	fn record(&self, started: Instant, packet_type: PacketType, bytes: usize, uncompressed: usize) {
+		self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+		self.bytes_uncompressed
+			.fetch_add(uncompressed as u64, Ordering::Relaxed);
+		self.packets.fetch_add(1, Ordering::Relaxed);
+		self.last_packet
+			.store(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+		*self
+			.packet_types
+			.lock()
+			.unwrap()
+			.entry(packet_type)
+			.or_insert(0) += 1;
+	}
+	fn last_packet(&self) -> Duration {
+		Duration::from_nanos(self.last_packet.load(Ordering::Relaxed))
+	}
+}
@@ -8,32 +8,56 @@
 #![feature(mapped_lock_guards)]
 
 use anyhow::bail;
-pub use closureslop::{self, add_callback};
+pub use closureslop::{self, add_callback, add_monitor, add_runtime_callback, add_runtime_monitor};
 pub use craftflow_macros::{callback, init, reg};
 
+pub mod config;
 pub mod connection;
+mod listener;
 pub mod modules;
 pub mod packet_events;
+pub mod text;
 pub mod various_events;
 
 use closureslop::Reactor;
-use connection::{ConnectionInterface, handle_new_conn};
+use config::Config;
+use connection::{
+	Compression, ConnectionInterface, EncodedPacket, can_reach, handle_new_conn, packet_state,
+};
 use craftflow_protocol::{PacketBuilder, S2C};
+use listener::{Incoming, Listener};
 use modules::Modules;
 use std::{
 	collections::HashMap,
 	fmt::Display,
+	net::SocketAddr,
 	ops::ControlFlow,
 	sync::{Arc, MappedRwLockReadGuard, RwLock, RwLockReadGuard},
 };
-use tokio::{net::TcpListener, spawn};
-use tracing::{error, info, trace};
-use various_events::{Disconnect, Init, NewConnection};
+use text::TextComponent;
+use tokio::{
+	io::{AsyncRead, AsyncWrite},
+	select,
+	sync::watch,
+	task::JoinSet,
+	time::timeout,
+};
+use tracing::{error, info, trace, warn};
+use various_events::{Disconnect, DisconnectReason, Init, Shutdown};
 
 pub struct CraftFlow {
+	config: Config,
 	connections: RwLock<Connections>,
 	pub modules: Modules,
 	pub reactor: Reactor<Arc<CraftFlow>>,
+	// Some(reason) once the server is shutting down
+	shutdown: Arc<watch::Sender<Option<String>>>,
+}
+
+/// A handle that allows to shut down the server, see [`CraftFlow::shutdown_handle`]
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+	sender: Arc<watch::Sender<Option<String>>>,
 }
 
 struct Connections {
@@ -45,23 +69,36 @@ struct Connections {
 pub struct ConnId(u64);
 
 impl CraftFlow {
+	/// Creates a new CraftFlow instance with the default configuration
 	pub fn new() -> Self {
+		Self::with_config(Config::new())
+	}
+	/// Creates a new CraftFlow instance with the given configuration
+	pub fn with_config(config: Config) -> Self {
+		let reactor = Reactor::new();
+		reactor.set_panic_policy(config.callback_panic_policy);
+
 		Self {
+			config,
 			connections: RwLock::new(Connections {
 				connections: HashMap::new(),
 				next_conn_id: 0,
 			}),
 			modules: Modules::new(),
-			reactor: Reactor::new(),
+			reactor,
+			shutdown: Arc::new(watch::channel(None).0),
 		}
 	}
 
 	/// Runs the CraftFlow server
-	pub async fn run(self) -> anyhow::Result<()> {
-		let craftflow = Arc::new(self);
-
+	///
+	/// Returns after the server was shut down using [`shutdown`][Self::shutdown]
+	pub async fn run(mut self) -> anyhow::Result<()> {
 		// Start accepting connections in this task
-		let listener = TcpListener::bind("0.0.0.0:25565").await?;
+		let listeners = Listener::bind_all(std::mem::take(&mut self.config.listeners)).await?;
+		let mut shutdown = self.shutdown.subscribe();
+
+		let craftflow = Arc::new(self);
 
 		if let ControlFlow::Break(msg) =
 			craftflow.reactor.trigger::<Init>(&craftflow, &mut ()).await
@@ -71,27 +108,122 @@ impl CraftFlow {
 
 		info!("Craftflow started.");
 
-		loop {
-			let (stream, socket_addr) = listener.accept().await?;
+		let mut connection_tasks = JoinSet::new();
 
-			// Emit the new connection event
-			if craftflow
-				.reactor
-				.trigger::<NewConnection>(&craftflow, &mut socket_addr.ip())
-				.await
-				.is_break()
-			{
-				continue;
-			}
+		let reason = loop {
+			select! {
+				r = Listener::accept_any(&listeners) => {
+					let (incoming, socket_addr) = r?;
 
-			let craftflow_clone = Arc::clone(&craftflow);
-			spawn(async move {
-				if let Err(e) = handle_new_conn(craftflow_clone, stream, socket_addr).await {
-					error!("handling new connection: {e:?}");
+					let craftflow_clone = Arc::clone(&craftflow);
+					connection_tasks.spawn(async move {
+						let result = match incoming {
+							Incoming::Tcp(stream) => {
+								craftflow_clone.accept_stream(stream, socket_addr).await
+							}
+							#[cfg(unix)]
+							Incoming::Unix(stream) => {
+								craftflow_clone.accept_stream(stream, socket_addr).await
+							}
+						};
+
+						if let Err(e) = result {
+							error!("handling new connection: {e:?}");
+						}
+					});
 				}
-			});
+				// clean up the finished connection tasks
+				Some(_) = connection_tasks.join_next() => {}
+				r = shutdown.wait_for(Option::is_some) => {
+					break r.expect("shutdown sender is never dropped").clone().unwrap();
+				}
+			}
+		};
+
+		// stop accepting new connections
+		drop(listeners);
+
+		craftflow
+			.shutdown_gracefully(reason, connection_tasks)
+			.await;
+
+		Ok(())
+	}
+	/// Handles a new client connection over any stream, returning when the connection is closed.
+	///
+	/// This is what [`run`][Self::run] uses for all the connections it accepts, but it can also be
+	/// used to serve connections from custom transports, or in-memory streams such as
+	/// [`tokio::io::duplex`]. `peer_addr` is the address of the other end of the stream, which
+	/// will be reported as the client's (unless replaced by the PROXY protocol, if enabled).
+	///
+	/// Connections handled this way directly (not by [`run`][Self::run]) will not be waited for when
+	/// shutting down, but they will still be sent the disconnect packets.
+	pub async fn accept_stream(
+		self: &Arc<Self>,
+		stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+		peer_addr: SocketAddr,
+	) -> anyhow::Result<()> {
+		handle_new_conn(Arc::clone(self), stream, peer_addr).await
+	}
+	/// Starts shutting down the server gracefully, with the given reason, which will be shown
+	/// to all connected clients.
+	///
+	/// The server will stop accepting new connections, emit the [`Shutdown`] event,
+	/// disconnect all clients and then [`run`][Self::run] will return.
+	///
+	/// Does nothing if the server is already shutting down.
+	pub fn shutdown(&self, reason: impl Into<String>) {
+		shutdown(&self.shutdown, reason.into());
+	}
+	/// Returns a handle that can be used to shut down the server from anywhere,
+	/// even after it was moved into [`run`][Self::run].
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		ShutdownHandle {
+			sender: Arc::clone(&self.shutdown),
 		}
 	}
+	async fn shutdown_gracefully(
+		self: &Arc<Self>,
+		mut reason: String,
+		mut connection_tasks: JoinSet<()>,
+	) {
+		info!("Shutting down: {reason}");
+
+		let _ = self.reactor.trigger::<Shutdown>(self, &mut reason).await;
+
+		let connections: Vec<_> = self.connections().values().cloned().collect();
+
+		let shutdown = async {
+			// ask all connections to send their disconnect packets and close
+			for conn in connections {
+				conn.set_disconnect_reason(DisconnectReason::Shutdown(reason.clone()));
+				conn.close(reason.as_str().into()).await;
+			}
+
+			// and wait for them to actually do it
+			while connection_tasks.join_next().await.is_some() {}
+		};
+
+		if timeout(self.config.shutdown_timeout, shutdown)
+			.await
+			.is_err()
+		{
+			warn!(
+				"{} connections did not close in time, dropping them",
+				connection_tasks.len()
+			);
+			connection_tasks.shutdown().await;
+
+			// the aborted connections didn't get to clean up after themselves
+			let remaining: Vec<_> = self.connections().keys().copied().collect();
+			for conn_id in remaining {
+				self.remove_connection(conn_id, DisconnectReason::Shutdown(reason.clone()))
+					.await;
+			}
+		}
+
+		info!("Craftflow stopped.");
+	}
 	/// Accesses the connection handle of the given connection ID
 	pub fn get(&self, conn_id: ConnId) -> Arc<ConnectionInterface> {
 		Arc::clone(&self.connections.read().unwrap().connections[&conn_id])
@@ -122,14 +254,106 @@ impl CraftFlow {
 
 		conn.send(packet).await;
 	}
+	/// Builds and sends a packet to all connections matching the filter.
+	///
+	/// The packet is built and serialized only once for each distinct protocol version and
+	/// compression settings among the matching connections, and the same bytes are sent to all
+	/// of them (encrypted individually).
+	///
+	/// Connections whose protocol version doesn't have the packet, or that can't reach the state of
+	/// the packet anymore, are skipped silently. Connections that will reach the state later get the
+	/// packet once they do, like with [`send`][ConnectionInterface::send].
+	///
+	/// WARNING: broadcasted packets do not trigger any packet events.
+	///
+	/// ```no_run
+	/// # use craftflow::{CraftFlow, connection::State};
+	/// # use craftflow_protocol::{disabled_versions, s2c::play::{KeepAliveBuilder, keep_alive::*}};
+	/// # async fn f(craftflow: &CraftFlow) {
+	/// craftflow
+	/// 	.broadcast(
+	/// 		|conn| conn.state() == State::Play,
+	/// 		|b| match b {
+	/// 			KeepAliveBuilder::V5(p) => p(v5::KeepAliveV5 { keep_alive_id: 7 }),
+	/// 			KeepAliveBuilder::V47(p) => p(v47::KeepAliveV47 { keep_alive_id: 7 }),
+	/// 			KeepAliveBuilder::V340(p) => p(v340::KeepAliveV340 { keep_alive_id: 7 }),
+	/// 			disabled_versions!(s2c::play::KeepAliveBuilder) => unreachable!(),
+	/// 		},
+	/// 	)
+	/// 	.await;
+	/// # }
+	/// ```
+	pub async fn broadcast<B: PacketBuilder>(
+		&self,
+		filter: impl Fn(&ConnectionInterface) -> bool,
+		f: impl Fn(B) -> B::Packet,
+	) where
+		B::Packet: Into<S2C>,
+	{
+		// group the connections by everything that affects the serialized bytes
+		let mut groups: HashMap<(u32, Option<Compression>), Vec<Arc<ConnectionInterface>>> =
+			HashMap::new();
+		for conn in self.connections().values() {
+			let version = conn.protocol_version();
+			if B::VERSIONS.contains(&version) && filter(conn) {
+				groups
+					.entry((version, conn.compression()))
+					.or_default()
+					.push(Arc::clone(conn));
+			}
+		}
+
+		for ((version, compression), mut conns) in groups {
+			let packet = f(B::new(version)).into();
+
+			// packets for a later state are held until the connection reaches it,
+			// but some connections will never get there
+			let state = packet_state(&packet);
+			conns.retain(|conn| {
+				let current = conn.state();
+				current == state || can_reach(current, state, version)
+			});
+			if conns.is_empty() {
+				continue;
+			}
+
+			let encoded = match EncodedPacket::new(
+				version,
+				compression,
+				self.config.offload_compression_size,
+				packet,
+			)
+			.await
+			{
+				Ok(encoded) => Arc::new(encoded),
+				Err(e) => {
+					error!("serializing broadcasted packet: {e:?}");
+					continue;
+				}
+			};
+
+			for conn in conns {
+				conn.send_encoded(Arc::clone(&encoded)).await;
+			}
+		}
+	}
 	/// Disconnects the client with the given connection ID
 	/// No-op if the client is already disconnected, panic if the client ID was never connected
 	pub async fn disconnect(self: &Arc<Self>, conn_id: ConnId) {
+		self.remove_connection(conn_id, DisconnectReason::ServerClosed)
+			.await;
+	}
+	/// Emits the disconnect event and removes the connection from the list, if it's still there
+	pub(crate) async fn remove_connection(
+		self: &Arc<Self>,
+		conn_id: ConnId,
+		reason: DisconnectReason,
+	) {
 		if self.connections.read().unwrap().is_connected(conn_id) {
 			// emit the disconnect event
 			let _ = self
 				.reactor
-				.trigger::<Disconnect>(self, &mut { conn_id })
+				.trigger::<Disconnect>(self, &mut (conn_id, reason))
 				.await;
 
 			self.connections
@@ -139,6 +363,22 @@ impl CraftFlow {
 				.remove(&conn_id);
 		}
 	}
+	/// Disconnects the client with the given connection ID, showing them the given reason, which
+	/// can be plain text or any [`TextComponent`].
+	///
+	/// The disconnect packet appropriate for the state and protocol version of the connection is
+	/// sent after all already queued packets, and then the connection is closed. In states that
+	/// have no disconnect packet (handshake and status) the connection is just closed.
+	///
+	/// No-op if the client is already disconnected.
+	pub async fn kick(&self, conn_id: ConnId, reason: impl Into<TextComponent>) {
+		let conn = self.connections().get(&conn_id).cloned();
+		if let Some(conn) = conn {
+			let reason = reason.into();
+			conn.set_disconnect_reason(DisconnectReason::Kicked(reason.clone()));
+			conn.close(reason).await;
+		}
+	}
 	/// Accesses the connections map
 	/// There is no mutable access because it is not meant to be modified directly
 	/// Use the `disconnect` method to disconnect a client
@@ -149,6 +389,24 @@ impl CraftFlow {
 	}
 }
 
+impl ShutdownHandle {
+	/// Starts shutting down the server gracefully, see [`CraftFlow::shutdown`]
+	pub fn shutdown(&self, reason: impl Into<String>) {
+		shutdown(&self.sender, reason.into());
+	}
+}
+
+fn shutdown(sender: &watch::Sender<Option<String>>, reason: String) {
+	sender.send_if_modified(|current| {
+		if current.is_some() {
+			return false; // already shutting down
+		}
+
+		*current = Some(reason);
+		true
+	});
+}
+
 impl Connections {
 	/// Checks if the given connection ID is connected
 	///
@@ -164,7 +422,7 @@ impl Connections {
 
 impl Default for CraftFlow {
 	fn default() -> Self {
-		todo!()
+		Self::new()
 	}
 }
 
@@ -0,0 +1,100 @@
+use crate::config::{DEFAULT_ADDRESS, ListenerConfig};
+use anyhow::Context;
+use futures::future::select_all;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::info;
+
+#[cfg(unix)]
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// A bound listener, accepting connections
+pub(crate) enum Listener {
+	Tcp(TcpListener),
+	#[cfg(unix)]
+	Unix(UnixListener),
+}
+
+/// A freshly accepted connection
+pub(crate) enum Incoming {
+	Tcp(TcpStream),
+	#[cfg(unix)]
+	Unix(UnixStream),
+}
+
+impl Listener {
+	/// Binds all configured listeners, or the default address if none configured
+	pub(crate) async fn bind_all(configs: Vec<ListenerConfig>) -> anyhow::Result<Vec<Self>> {
+		let configs = if configs.is_empty() {
+			vec![ListenerConfig::Bind(DEFAULT_ADDRESS)]
+		} else {
+			configs
+		};
+
+		let mut listeners = Vec::with_capacity(configs.len());
+		for config in configs {
+			let listener = match config {
+				ListenerConfig::Bind(address) => Listener::Tcp(
+					TcpListener::bind(address)
+						.await
+						.with_context(|| format!("binding {address}"))?,
+				),
+				ListenerConfig::Tcp(listener) => Listener::Tcp(listener),
+				#[cfg(unix)]
+				ListenerConfig::BindUnix(path) => Listener::Unix(
+					UnixListener::bind(&path)
+						.with_context(|| format!("binding {}", path.display()))?,
+				),
+				#[cfg(unix)]
+				ListenerConfig::Unix(listener) => Listener::Unix(listener),
+			};
+
+			info!("Listening on {}", listener.describe());
+			listeners.push(listener);
+		}
+
+		Ok(listeners)
+	}
+	/// Accepts a new connection from any of the given listeners (Cancel-safe)
+	pub(crate) async fn accept_any(listeners: &[Self]) -> std::io::Result<(Incoming, SocketAddr)> {
+		let (result, _, _) = select_all(listeners.iter().map(|l| Box::pin(l.accept()))).await;
+
+		result
+	}
+	async fn accept(&self) -> std::io::Result<(Incoming, SocketAddr)> {
+		match self {
+			Listener::Tcp(listener) => {
+				let (stream, address) = listener.accept().await?;
+				Ok((Incoming::Tcp(stream), address))
+			}
+			#[cfg(unix)]
+			Listener::Unix(listener) => {
+				// Unix sockets dont have an IP address, so just pretend they're coming from localhost
+				let (stream, _) = listener.accept().await?;
+				Ok((
+					Incoming::Unix(stream),
+					SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+				))
+			}
+		}
+	}
+	fn describe(&self) -> String {
+		match self {
+			Listener::Tcp(listener) => match listener.local_addr() {
+				Ok(address) => address.to_string(),
+				Err(_) => "<unknown TCP address>".to_string(),
+			},
+			#[cfg(unix)]
+			Listener::Unix(listener) => match listener
+				.local_addr()
+				.ok()
+				.and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+			{
+				Some(path) => format!("unix:{path}"),
+				None => "<unnamed unix socket>".to_string(),
+			},
+		}
+	}
+}
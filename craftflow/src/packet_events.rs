@@ -3,6 +3,9 @@
 //!  - [`S2C`] packet events will be emitted before a packet is sent to the client
 //!  - [`Post<S2C>`] events will be emitted AFTER a packet is sent to the client
 //!  - [`Post<C2S>`] events will be emitted after the respective [`C2S`] event is over, if it wasn't stopped
+//!  - [`UnknownPacket`] events will be emitted for received packets that are not known to the protocol crate
+//!  - [`AnyPacket`] events will be emitted before the events of the specific packets, for all packets
+//!    of a direction or state
 
 // BEWARE!
 // nuclear code below!!
@@ -21,9 +24,10 @@
 
 use crate::ConnId;
 use crate::CraftFlow;
-use closureslop::Event;
-use craftflow_protocol::{C2S, S2C, impl_for};
-use std::sync::Arc;
+use crate::connection::State;
+use closureslop::{Event, Member};
+use craftflow_protocol::{C2S, S2C, c2s, impl_for, s2c};
+use std::{any::type_name, fmt::Debug, sync::Arc};
 use tracing::trace;
 
 /// Event wrapper for packets
@@ -53,6 +57,80 @@ impl<E: Event> Event for Post<E> {
 	type Return = E::Return;
 }
 
+/// Event for all packets of a direction ([`C2S`] or [`S2C`]) or of a state (e.g. [`s2c::Play`]),
+/// with a type-erased view of the packet. Useful for logging or counting packets.
+///
+/// These events are triggered before the events of the specific packets, and stopping them stops
+/// the packet too. The state events are members of the direction events (see [`Member`]), so
+/// for example `AnyPacket<S2C>` is triggered before `AnyPacket<s2c::Play>`.
+/// The same goes for `Post<AnyPacket<...>>` events.
+pub struct AnyPacket<T> {
+	_packet: T,
+}
+
+impl_for! {direction:
+impl Event for AnyPacket<direction> {
+	type Args<'a> = (ConnId, PacketView<'a>);
+	type Return = ();
+}}
+impl_for! {state:
+impl Event for AnyPacket<state> {
+	type Args<'a> = (ConnId, PacketView<'a>);
+	type Return = ();
+}}
+
+macro_rules! members_of {
+	($direction:ty: $($state:ty),*) => {$(
+		impl Member<AnyPacket<$direction>> for AnyPacket<$state> {
+			fn group_args<'a>(args: &'a (ConnId, PacketView<'_>)) -> (ConnId, PacketView<'a>) {
+				*args
+			}
+		}
+	)*};
+}
+members_of!(C2S: c2s::Handshaking, c2s::Status, c2s::Login, c2s::Configuration, c2s::Play);
+members_of!(S2C: s2c::Status, s2c::Login, s2c::Configuration, s2c::Play);
+
+impl<E: Member<G>, G: Event> Member<Post<G>> for Post<E> {
+	fn group_args<'a>(args: &'a E::Args<'_>) -> G::Args<'a> {
+		E::group_args(args)
+	}
+}
+
+/// A type-erased view of a packet, see [`AnyPacket`]
+#[derive(Debug, Clone, Copy)]
+pub struct PacketView<'a> {
+	/// The name of the packet type, e.g. `KeepAlive`
+	pub name: &'static str,
+	pub state: State,
+	/// The protocol version of the connection
+	pub protocol_version: u32,
+	pub packet: &'a (dyn Debug + Sync),
+}
+
+/// This event is triggered when a packet with an ID that is not known to the protocol crate
+/// is received, so that it can still be handled.
+///
+/// Stop the event if the packet was handled. Otherwise, with
+/// [`Config::strict_protocol`][crate::config::Config::strict_protocol] the connection is closed.
+pub struct UnknownPacket;
+
+/// A packet that is not parsed, see also [`ConnectionInterface::send_raw`][crate::connection::ConnectionInterface::send_raw]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawPacket {
+	/// The state that the packet belongs to
+	pub state: State,
+	/// The packet ID
+	pub id: u32,
+	/// The packet data after the ID, decompressed
+	pub bytes: Vec<u8>,
+}
+
+impl Event for UnknownPacket {
+	type Args<'a> = (ConnId, RawPacket);
+	type Return = ();
+}
+
 // Helper functions that trigger a packet event
 // returns true if the event was not stopped
 async fn helper<'a, P>(craftflow: &Arc<CraftFlow>, conn_id: ConnId, packet: P) -> (bool, P)
@@ -90,8 +168,120 @@ where
 	(true, args.1)
 }
 
+// Triggers the AnyPacket events of the state and the direction
+// returns true if they were not stopped
+async fn helper_any<S, D>(
+	post: bool,
+	craftflow: &Arc<CraftFlow>,
+	conn_id: ConnId,
+	name: &'static str,
+	state: State,
+	packet: &(dyn Debug + Sync),
+) -> bool
+where
+	AnyPacket<S>:
+		Member<AnyPacket<D>> + for<'a> Event<Args<'a> = (ConnId, PacketView<'a>), Return = ()>,
+	AnyPacket<D>: Event<Return = ()>,
+{
+	let reactor = &craftflow.reactor;
+
+	// the view needs a connection lookup, so only build it if anyone is listening
+	let listening = match post {
+		false => reactor.has_callbacks::<AnyPacket<S>>() || reactor.has_callbacks::<AnyPacket<D>>(),
+		true => {
+			reactor.has_callbacks::<Post<AnyPacket<S>>>()
+				|| reactor.has_callbacks::<Post<AnyPacket<D>>>()
+		}
+	};
+	if !listening {
+		return true;
+	}
+	let Some(protocol_version) = craftflow
+		.connections()
+		.get(&conn_id)
+		.map(|conn| conn.protocol_version())
+	else {
+		return true;
+	};
+
+	let view = PacketView {
+		name,
+		state,
+		protocol_version,
+		packet,
+	};
+	let mut args = (conn_id, view);
+
+	let result = match post {
+		false => {
+			reactor
+				.trigger_with_group::<AnyPacket<S>, AnyPacket<D>>(craftflow, &mut args)
+				.await
+		}
+		true => {
+			reactor
+				.trigger_with_group::<Post<AnyPacket<S>>, Post<AnyPacket<D>>>(craftflow, &mut args)
+				.await
+		}
+	};
+
+	result.is_continue()
+}
+
+// the name of the type without the path
+fn short_type_name<T>(_: &T) -> &'static str {
+	let name = type_name::<T>();
+	name.rsplit("::").next().unwrap_or(name)
+}
+
 // More slop below
 
+async fn trigger_any_c2s(
+	post: bool,
+	craftflow: &Arc<CraftFlow>,
+	conn_id: ConnId,
+	packet: &C2S,
+) -> bool {
+	let name =
+		craftflow_protocol::enum_go_brr!((c2s->packet), packet -> inner short_type_name(inner));
+	let inner = craftflow_protocol::enum_go_brr!((c2s->version), packet -> inner inner as &(dyn Debug + Sync));
+
+	macro_rules! any {
+		($state:ty, $state_value:expr) => {
+			helper_any::<$state, C2S>(post, craftflow, conn_id, name, $state_value, inner).await
+		};
+	}
+	match packet {
+		C2S::Handshaking(_) => any!(c2s::Handshaking, State::Handshake),
+		C2S::Status(_) => any!(c2s::Status, State::Status),
+		C2S::Login(_) => any!(c2s::Login, State::Login),
+		C2S::Configuration(_) => any!(c2s::Configuration, State::Configuration),
+		C2S::Play(_) => any!(c2s::Play, State::Play),
+	}
+}
+async fn trigger_any_s2c(
+	post: bool,
+	craftflow: &Arc<CraftFlow>,
+	conn_id: ConnId,
+	packet: &S2C,
+) -> bool {
+	let name =
+		craftflow_protocol::enum_go_brr!((s2c->packet), packet -> inner short_type_name(inner));
+	let inner = craftflow_protocol::enum_go_brr!((s2c->version), packet -> inner inner as &(dyn Debug + Sync));
+
+	macro_rules! any {
+		($state:ty, $state_value:expr) => {
+			helper_any::<$state, S2C>(post, craftflow, conn_id, name, $state_value, inner).await
+		};
+	}
+	match packet {
+		S2C::Status(_) => any!(s2c::Status, State::Status),
+		S2C::Login(_) => any!(s2c::Login, State::Login),
+		S2C::Configuration(_) => any!(s2c::Configuration, State::Configuration),
+		S2C::Play(_) => any!(s2c::Play, State::Play),
+	}
+}
+
 pub(super) async fn trigger_c2s(
 	post: bool,
 	craftflow: &Arc<CraftFlow>,
@@ -102,6 +292,10 @@ pub(super) async fn trigger_c2s(
 		trace!("<- RECV {packet:?}");
 	}
 
+	if !trigger_any_c2s(post, craftflow, conn_id, &packet).await {
+		return (false, packet);
+	}
+
 	let (cont, pkt) = craftflow_protocol::enum_go_brr!((c2s->version), packet -> inner {
 		let (cont, pkt) = if !post { helper(craftflow, conn_id, inner).await } else { helper_post(craftflow, conn_id, inner).await };
 		(cont, pkt.into())
@@ -116,6 +310,19 @@ pub(super) async fn trigger_c2s(
 		(cont, pkt)
 	}
 }
+/// Whether there are any callbacks for the events of the specific packet, which could modify it
+pub(super) fn has_s2c_callbacks(craftflow: &CraftFlow, packet: &S2C) -> bool {
+	let version = craftflow_protocol::enum_go_brr!((s2c->version), packet -> inner has_callbacks(craftflow, inner));
+	let packet = craftflow_protocol::enum_go_brr!((s2c->packet), packet -> inner has_callbacks(craftflow, inner));
+
+	version || packet
+}
+fn has_callbacks<P>(craftflow: &CraftFlow, _packet: &P) -> bool
+where
+	Packet<P>: Event,
+{
+	craftflow.reactor.has_callbacks::<Packet<P>>()
+}
 pub(super) async fn trigger_s2c(
 	post: bool,
 	craftflow: &Arc<CraftFlow>,
@@ -126,6 +333,10 @@ pub(super) async fn trigger_s2c(
 		trace!("-> SENT {packet:?}");
 	}
 
+	if !trigger_any_s2c(post, craftflow, conn_id, &packet).await {
+		return (false, packet);
+	}
+
 	let (cont, pkt) = craftflow_protocol::enum_go_brr!((s2c->version), packet -> inner {
 		let (cont, pkt) = if !post { helper(craftflow, conn_id, inner).await } else { helper_post(craftflow, conn_id, inner).await };
 		(cont, pkt.into())
@@ -0,0 +1,172 @@
+//! Text components, the formatted text that is shown to clients
+
+use craftflow_protocol::craftflow_nbt::{NbtCompound, NbtList, NbtString, NbtValue};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::{self, Debug, Display};
+
+/// A text component, for example the reason shown to a kicked client.
+///
+/// Can be made from plain text directly, or from any serializable text component
+/// (like the one in the `text` module) with [`TextComponent::new`].
+/// It is serialized as JSON or NBT, depending on what the protocol version of the client expects.
+#[derive(Clone, PartialEq)]
+pub struct TextComponent(Value);
+
+impl TextComponent {
+	/// Makes a text component from anything that serializes to a JSON text component
+	pub fn new(component: &impl Serialize) -> serde_json::Result<Self> {
+		serde_json::to_value(component).map(Self)
+	}
+	/// Serializes the text component to a JSON string
+	pub fn to_json(&self) -> String {
+		self.0.to_string()
+	}
+	/// Converts the text component to NBT, as used since 1.20.3 (765).
+	///
+	/// Returns `None` if a string in it is too long for NBT.
+	pub fn to_nbt(&self) -> Option<NbtValue> {
+		json_to_nbt(&self.0)
+	}
+}
+
+impl From<&str> for TextComponent {
+	fn from(text: &str) -> Self {
+		Self(Value::String(text.to_owned()))
+	}
+}
+impl From<String> for TextComponent {
+	fn from(text: String) -> Self {
+		Self(Value::String(text))
+	}
+}
+impl From<Value> for TextComponent {
+	fn from(component: Value) -> Self {
+		Self(component)
+	}
+}
+
+impl Display for TextComponent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		Display::fmt(&self.0, f)
+	}
+}
+impl Debug for TextComponent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		Display::fmt(&self.0, f)
+	}
+}
+
+// null values are skipped, since NBT has no such thing
+fn json_to_nbt(value: &Value) -> Option<NbtValue> {
+	let nbt = match value {
+		Value::Null => return None,
+		Value::Bool(b) => NbtValue::Byte(*b as i8),
+		Value::Number(n) => match n.as_i64() {
+			Some(n) => match i32::try_from(n) {
+				Ok(n) => NbtValue::Int(n),
+				Err(_) => NbtValue::Long(n),
+			},
+			None => NbtValue::Double(n.as_f64()?),
+		},
+		Value::String(s) => NbtValue::String(NbtString::from_str(s).ok()?),
+		Value::Array(values) => {
+			let values = values
+				.iter()
+				.filter(|v| !v.is_null())
+				.map(json_to_nbt)
+				.collect::<Option<Vec<_>>>()?;
+			NbtValue::List(nbt_list(values)?)
+		}
+		Value::Object(map) => {
+			let mut compound = NbtCompound::new();
+			for (key, value) in map {
+				if value.is_null() {
+					continue;
+				}
+				compound.insert(NbtString::from_str(key).ok()?, json_to_nbt(value)?);
+			}
+			NbtValue::Compound(compound)
+		}
+	};
+
+	Some(nbt)
+}
+
+// NBT lists must have elements of a single type, so in mixed lists all elements are wrapped
+// in compounds with an empty key, which the client unwraps
+fn nbt_list(values: Vec<NbtValue>) -> Option<NbtList> {
+	if values.iter().all(|v| matches!(v, NbtValue::Compound(_))) {
+		return Some(NbtList::Compound(
+			values
+				.into_iter()
+				.filter_map(|v| v.try_into().ok())
+				.collect(),
+		));
+	}
+	if values.iter().all(|v| matches!(v, NbtValue::String(_))) {
+		return Some(NbtList::String(
+			values
+				.into_iter()
+				.filter_map(|v| v.try_into().ok())
+				.collect(),
+		));
+	}
+
+	let empty_key = NbtString::from_str("").ok()?;
+	Some(NbtList::Compound(
+		values
+			.into_iter()
+			.map(|v| NbtCompound::from([(empty_key.clone(), v)]))
+			.collect(),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn string(s: &str) -> NbtValue {
+		NbtValue::try_from(s).unwrap()
+	}
+	fn key(s: &str) -> NbtString {
+		NbtString::from_str(s).unwrap()
+	}
+
+	#[test]
+	fn test_to_nbt() {
+		assert_eq!(TextComponent::from("bye").to_nbt(), Some(string("bye")));
+
+		let component = TextComponent::from(json!({
+			"text": "bye",
+			"bold": true,
+			"color": null,
+			"extra": ["a", {"text": "b"}],
+		}));
+		let mut expected = NbtCompound::new();
+		expected.insert(key("text"), string("bye"));
+		expected.insert(key("bold"), NbtValue::Byte(1));
+		let mut b = NbtCompound::new();
+		b.insert(key("text"), string("b"));
+		expected.insert(
+			key("extra"),
+			NbtValue::List(NbtList::Compound(vec![
+				NbtCompound::from([(key(""), string("a"))]),
+				NbtCompound::from([(key(""), NbtValue::Compound(b))]),
+			])),
+		);
+		assert_eq!(component.to_nbt(), Some(NbtValue::Compound(expected)));
+
+		assert_eq!(
+			component.to_json(),
+			r#"{"bold":true,"color":null,"extra":["a",{"text":"b"}],"text":"bye"}"#
+		);
+	}
+
+	#[test]
+	fn test_too_long() {
+		let long = "a".repeat(u16::MAX as usize + 1);
+		assert_eq!(TextComponent::from(long).to_nbt(), None);
+	}
+}
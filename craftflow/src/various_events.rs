@@ -1,25 +1,96 @@
-use crate::ConnId;
+use crate::{
+	ConnId,
+	config::OverflowPolicy,
+	connection::{HandshakeInfo, State},
+	text::TextComponent,
+};
 use closureslop::Event;
-use std::net::IpAddr;
+use std::{io, net::IpAddr};
 
 /// The first event that is triggered right after craftflow is started
 pub struct Init;
 
 /// This event is triggered when a new connection is established.
 ///
-/// This event triggers before anything is even read/sent to the client or even added to the connection list
-/// and given an id.
+/// This event triggers before anything is even read/sent to the client (except the PROXY protocol header,
+/// if enabled) or even added to the connection list and given an id.
 pub struct NewConnection;
 
-/// This event is triggered when a connection is closed.
+/// This event is triggered when the handshake of a new connection is received, before it switches
+/// to the next state and is added to the connection list.
+///
+/// The handshake info can be changed, for example to route the connection to a different host name
+/// or intent. It is then available with [`ConnectionInterface::handshake`][crate::connection::ConnectionInterface::handshake].
+pub struct Handshake;
+
+/// This event is triggered when a connection is closed, with the reason why.
 pub struct Disconnect;
 
+/// Why a connection was closed
+#[derive(Debug)]
+pub enum DisconnectReason {
+	/// The client closed the connection cleanly
+	ClientClosed,
+	/// The client sent invalid data, or a packet could not be sent to it
+	ProtocolError(anyhow::Error),
+	/// Reading from or writing to the connection failed.
+	///
+	/// The error is caused by an [`io::Error`] (see [`anyhow::Error::downcast_ref`]), with all the
+	/// context that was added to it.
+	Io(anyhow::Error),
+	/// The connection timed out
+	Timeout,
+	/// The client was kicked with the given message, see [`CraftFlow::kick`][crate::CraftFlow::kick]
+	Kicked(TextComponent),
+	/// The server is shutting down, with the given message
+	Shutdown(String),
+	/// The client didn't read the packets fast enough, see [`OverflowPolicy::Disconnect`]
+	OutboundOverflow,
+	/// The server closed the connection without a message, for example with
+	/// [`CraftFlow::disconnect`][crate::CraftFlow::disconnect]
+	ServerClosed,
+}
+
 /// This event is triggered when a client tries to connect with an unsupported protocol version.
 pub struct UnsupportedClientVersion;
 
 /// This event is triggered when the connection state is set to Play
 pub struct EnterPlayState;
 
+/// This event is triggered when the state of either half of a connection changes.
+///
+/// Since 1.20.2 the halves change state separately: the writing half when the server sends a
+/// state changing packet, and the reading half when the client acknowledges it.
+pub struct StateChanged;
+
+/// A state change of one half of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChange {
+	pub from: State,
+	pub to: State,
+	pub half: ConnectionHalf,
+}
+
+/// One of the two directions of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionHalf {
+	/// Packets from the client to the server
+	Reader,
+	/// Packets from the server to the client
+	Writer,
+}
+
+/// This event is triggered when the server starts shutting down, after it stops accepting
+/// new connections but before the connected clients are disconnected.
+pub struct Shutdown;
+
+/// This event is triggered when the outbound packet queue of a client overflows, because it doesn't
+/// read the packets fast enough.
+///
+/// Triggered once per overflow, and not again until the queue is drained.
+/// If the policy is [`OverflowPolicy::Disconnect`], the connection is already being closed.
+pub struct OutboundOverflow;
+
 impl Event for Init {
 	type Args<'a> = ();
 	// If event stopped, craftflow will not start and display the given message
@@ -33,9 +104,17 @@ impl Event for NewConnection {
 	type Return = ();
 }
 
+impl Event for Handshake {
+	/// The IP of the connection, the protocol version and the handshake info
+	type Args<'a> = (IpAddr, u32, HandshakeInfo);
+	/// If the event is blocked, the connection will be closed, showing the given message to the client
+	/// if it is logging in
+	type Return = TextComponent;
+}
+
 impl Event for Disconnect {
-	/// The ID of the connection that was closed.
-	type Args<'a> = ConnId;
+	/// The ID of the connection that was closed and the reason
+	type Args<'a> = (ConnId, DisconnectReason);
 	type Return = ();
 }
 
@@ -52,3 +131,61 @@ impl Event for EnterPlayState {
 	type Args<'a> = ConnId;
 	type Return = ();
 }
+
+impl Event for StateChanged {
+	/// The connection ID and the state change
+	type Args<'a> = (ConnId, StateChange);
+	type Return = ();
+}
+
+impl Event for Shutdown {
+	/// The reason of the shutdown, that will be shown to all connected clients
+	type Args<'a> = String;
+	type Return = ();
+}
+
+impl Event for OutboundOverflow {
+	/// The connection ID and the overflow policy that was applied
+	type Args<'a> = (ConnId, OverflowPolicy);
+	type Return = ();
+}
+
+impl DisconnectReason {
+	/// Classifies an error that ended one of the connection tasks
+	pub(crate) fn from_error(error: anyhow::Error) -> Self {
+		match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+			// invalid data is the client's fault, not an I/O problem
+			Some(io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput) | None => {
+				Self::ProtocolError(error)
+			}
+			Some(io::ErrorKind::TimedOut) => Self::Timeout,
+			Some(_) => Self::Io(error),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_from_error() {
+		let error = anyhow::Error::from(io::Error::from(io::ErrorKind::BrokenPipe))
+			.context("failed to write packets");
+		let DisconnectReason::Io(error) = DisconnectReason::from_error(error) else {
+			panic!("expected an I/O error");
+		};
+		// the context is kept
+		assert_eq!(error.to_string(), "failed to write packets");
+		assert_eq!(
+			error.downcast_ref::<io::Error>().unwrap().kind(),
+			io::ErrorKind::BrokenPipe
+		);
+
+		let error = anyhow::Error::from(io::Error::from(io::ErrorKind::InvalidData));
+		assert!(matches!(
+			DisconnectReason::from_error(error),
+			DisconnectReason::ProtocolError(_)
+		));
+	}
+}
@@ -0,0 +1,1017 @@
+//! Drives whole connections over in-memory streams, without opening any sockets
+
+use craftflow::{
+	ConnId, CraftFlow, add_runtime_callback, callback,
+	config::{Config, OutboundQueueConfig, PanicPolicy},
+	connection::{HandshakeInfo, Intent, PacketType, State},
+	packet_events::{AnyPacket, Packet, Post, RawPacket, UnknownPacket},
+	reg,
+	text::TextComponent,
+	various_events::{
+		ConnectionHalf, Disconnect, DisconnectReason, Handshake, Shutdown, StateChange,
+		StateChanged,
+	},
+};
+use craftflow_protocol::{
+	C2S, PacketRead, PacketWrite,
+	c2s::{
+		self,
+		configuration::finish_configuration::v764::FinishConfigurationV764 as FinishConfigurationAck,
+		handshaking::set_protocol::v5::SetProtocolV5,
+		login::{
+			LoginAcknowledged, login_acknowledged::v764::LoginAcknowledgedV764,
+			login_start::v764::LoginStartV764,
+		},
+		play::configuration_acknowledged::v764::ConfigurationAcknowledgedV764,
+		status::{Ping, PingStart, ping::v5::PingV5, ping_start::v5::PingStartV5},
+	},
+	craftflow_nbt::NbtValue,
+	disabled_versions,
+	s2c::{
+		self,
+		configuration::{
+			FinishConfigurationBuilder, finish_configuration::v764::FinishConfigurationV764,
+		},
+		play::{
+			KeepAliveBuilder, StartConfigurationBuilder, keep_alive,
+			start_configuration::v764::StartConfigurationV764,
+		},
+		status::{
+			self as s2c_status, PingBuilder, ServerInfoBuilder, server_info::v5::ServerInfoV5,
+		},
+	},
+};
+use craftflow_test_support::{
+	UUID, VERSION, connect, handshake, login, parse_packet, read_frame, read_packet, write_frame,
+	write_packet,
+};
+use serde_json::json;
+use smallbox::SmallBox;
+use std::{
+	io,
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	ops::ControlFlow,
+	pin::Pin,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+	},
+	task::{Context, Poll},
+	time::Duration,
+};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf, duplex},
+	net::{TcpListener, TcpStream},
+	spawn,
+	time::{sleep, timeout},
+};
+
+craftflow::init!();
+
+/// A stream that counts how many times it was written to
+struct CountingStream {
+	stream: DuplexStream,
+	writes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for CountingStream {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for CountingStream {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		let result = Pin::new(&mut self.stream).poll_write(cx, buf);
+		if result.is_ready() {
+			self.writes.fetch_add(1, Ordering::Relaxed);
+		}
+		result
+	}
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_flush(cx)
+	}
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_shutdown(cx)
+	}
+}
+
+/// The reasons of all disconnects, formatted
+struct DisconnectReasons(Mutex<Vec<String>>);
+
+/// All state changes of all connections
+struct StateChanges(Mutex<Vec<StateChange>>);
+
+/// All received unknown packets
+struct UnknownPackets(Mutex<Vec<RawPacket>>);
+
+#[callback(event: Packet<PingStart>)]
+async fn server_info(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, _): &mut (ConnId, PingStart),
+) -> ControlFlow<()> {
+	cf.build_packet(conn_id, |b| match b {
+		ServerInfoBuilder::V5(p) => p(ServerInfoV5 {
+			response: r#"{"description":"in memory"}"#.to_owned(),
+		}),
+		disabled_versions!(s2c::status::ServerInfoBuilder) => unreachable!(),
+	})
+	.await;
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Packet<Ping>)]
+async fn pong(cf: &Arc<CraftFlow>, (conn_id, request): &mut (ConnId, Ping)) -> ControlFlow<()> {
+	let Ping::V5(request) = request else {
+		unreachable!()
+	};
+	let time = request.time;
+
+	cf.build_packet(*conn_id, |b| match b {
+		PingBuilder::V5(p) => p(s2c_status::ping::v5::PingV5 { time }),
+		disabled_versions!(s2c::status::PingBuilder) => unreachable!(),
+	})
+	.await;
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Packet<LoginAcknowledged>)]
+async fn login_acknowledged(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, _): &mut (ConnId, LoginAcknowledged),
+) -> ControlFlow<()> {
+	cf.build_packet(conn_id, |b| match b {
+		FinishConfigurationBuilder::V764(p) => p(FinishConfigurationV764),
+		disabled_versions!(s2c::configuration::FinishConfigurationBuilder) => unreachable!(),
+	})
+	.await;
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Disconnect)]
+async fn disconnect(
+	cf: &Arc<CraftFlow>,
+	(_, reason): &mut (ConnId, DisconnectReason),
+) -> ControlFlow<()> {
+	let reasons = cf.modules.get::<DisconnectReasons>();
+	reasons.0.lock().unwrap().push(format!("{reason:?}"));
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Handshake)]
+async fn handshake_received(
+	_cf: &Arc<CraftFlow>,
+	(_, _, info): &mut (IpAddr, u32, HandshakeInfo),
+) -> ControlFlow<TextComponent> {
+	if info.server_address == "blocked.localhost" {
+		return ControlFlow::Break(json!({"text": "go away", "color": "red"}).into());
+	}
+	if let Some(extra) = info.server_address.strip_prefix("old.localhost.") {
+		info.server_address = format!("new.localhost.{extra}");
+	}
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: UnknownPacket)]
+async fn unknown_packet(
+	cf: &Arc<CraftFlow>,
+	(_, packet): &mut (ConnId, RawPacket),
+) -> ControlFlow<()> {
+	cf.modules
+		.get::<UnknownPackets>()
+		.0
+		.lock()
+		.unwrap()
+		.push(packet.clone());
+
+	// pretend to handle only this one
+	if packet.id == 0x7e {
+		return ControlFlow::Break(());
+	}
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: StateChanged)]
+async fn state_changed(
+	cf: &Arc<CraftFlow>,
+	&mut (_, change): &mut (ConnId, StateChange),
+) -> ControlFlow<()> {
+	cf.modules
+		.get::<StateChanges>()
+		.0
+		.lock()
+		.unwrap()
+		.push(change);
+
+	ControlFlow::Continue(())
+}
+
+fn craftflow() -> Arc<CraftFlow> {
+	craftflow_with(Config::new())
+}
+
+fn craftflow_with(config: Config) -> Arc<CraftFlow> {
+	Arc::new(server(config))
+}
+
+/// A server that is not running yet
+fn server(config: Config) -> CraftFlow {
+	let mut craftflow = CraftFlow::with_config(config);
+	craftflow
+		.modules
+		.register(DisconnectReasons(Mutex::new(Vec::new())));
+	craftflow
+		.modules
+		.register(StateChanges(Mutex::new(Vec::new())));
+	craftflow
+		.modules
+		.register(UnknownPackets(Mutex::new(Vec::new())));
+	craftflow_test_support::register(&mut craftflow);
+	reg!(to: &mut craftflow.reactor);
+
+	craftflow
+}
+
+/// Writes a packet below the compression threshold, after compression was enabled
+async fn write_small_packet(client: &mut (impl AsyncWrite + Unpin), packet: &impl PacketWrite) {
+	// the uncompressed length is 0 if the packet is not compressed
+	let mut data = vec![0];
+	packet.packet_write(&mut data, VERSION);
+
+	write_frame(client, &data).await;
+}
+
+/// Writes an uncompressed and unencrypted packet that is too small to need a multi-byte length
+async fn write_raw_packet(client: &mut (impl AsyncWrite + Unpin), id: u8, data: &[u8]) {
+	client.write_u8(1 + data.len() as u8).await.unwrap();
+	client.write_u8(id).await.unwrap();
+	client.write_all(data).await.unwrap();
+}
+
+fn disconnect_reasons(craftflow: &CraftFlow) -> Vec<String> {
+	craftflow
+		.modules
+		.get::<DisconnectReasons>()
+		.0
+		.lock()
+		.unwrap()
+		.clone()
+}
+
+/// Reads a packet below the compression threshold, after compression was enabled
+async fn read_small_packet<P: for<'a> PacketRead<'a>>(client: &mut (impl AsyncRead + Unpin)) -> P {
+	let data = read_frame(client).await;
+	assert_eq!(data[0], 0, "packet is compressed");
+
+	parse_packet(&data[1..])
+}
+
+#[tokio::test]
+async fn status() {
+	let craftflow = craftflow();
+	let (mut client, task) = connect(&craftflow);
+
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+
+	let s2c::Status::ServerInfo(s2c_status::ServerInfo::V5(info)) = read_packet(&mut client).await
+	else {
+		panic!("expected server info");
+	};
+	assert_eq!(info.response, r#"{"description":"in memory"}"#);
+
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1234);
+
+	drop(client);
+	task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+}
+
+#[tokio::test]
+async fn byte_limited_queue() {
+	// packets are serialized when queued to measure them, and serialized again if modified
+	let config = Config::new().outbound_queue(OutboundQueueConfig::new().max_bytes(64 * 1024));
+	let craftflow = craftflow_with(config);
+	let _modify = add_runtime_callback!(craftflow.reactor, Packet<s2c_status::Ping> => "modify" => |_cf, (_conn_id, pong)| {
+		let s2c_status::Ping::V5(pong) = pong else {
+			unreachable!()
+		};
+		pong.time += 1;
+		SmallBox::new(async { ControlFlow::Continue(()) })
+	});
+	let (mut client, task) = connect(&craftflow);
+
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+
+	let s2c::Status::ServerInfo(s2c_status::ServerInfo::V5(info)) = read_packet(&mut client).await
+	else {
+		panic!("expected server info");
+	};
+	assert_eq!(info.response, r#"{"description":"in memory"}"#);
+
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1235);
+
+	drop(client);
+	task.await.unwrap().unwrap();
+}
+
+// the clock only advances when all tasks are idle, so the pings always land in the same batch
+#[tokio::test(start_paused = true)]
+async fn batching() {
+	let config = Config::new().max_batch_latency(Duration::from_millis(200));
+	let craftflow = craftflow_with(config);
+
+	let (mut client, server) = duplex(64 * 1024);
+	let writes = Arc::new(AtomicUsize::new(0));
+	let server = CountingStream {
+		stream: server,
+		writes: Arc::clone(&writes),
+	};
+	let peer_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345);
+	let craftflow_ref = Arc::clone(&craftflow);
+	let task = spawn(async move { craftflow_ref.accept_stream(server, peer_addr).await });
+
+	// the post-send event is only triggered once the batch is written
+	let writes_when_sent = Arc::new(Mutex::new(Vec::new()));
+	let (writes_ref, sent_ref) = (Arc::clone(&writes), Arc::clone(&writes_when_sent));
+	let _sent = add_runtime_callback!(craftflow.reactor, Post<Packet<s2c_status::ServerInfo>> => "sent" => move |_cf, _args| {
+		sent_ref.lock().unwrap().push(writes_ref.load(Ordering::Relaxed));
+		SmallBox::new(async { ControlFlow::Continue(()) })
+	});
+
+	// both responses are sent within the batch latency
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+	sleep(Duration::from_millis(50)).await;
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+
+	let s2c::Status::ServerInfo(_) = read_packet(&mut client).await else {
+		panic!("expected server info");
+	};
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1234);
+	assert_eq!(writes.load(Ordering::Relaxed), 1);
+
+	drop(client);
+	task.await.unwrap().unwrap();
+	assert_eq!(*writes_when_sent.lock().unwrap(), [1]);
+}
+
+#[tokio::test]
+async fn login_and_configuration() {
+	let craftflow = craftflow();
+	let (mut client, task) = connect(&craftflow);
+
+	login(&mut client).await;
+
+	match read_packet::<s2c::Login>(&mut client).await {
+		s2c::Login::Success(s2c::login::Success::V759(success)) => {
+			assert_eq!(success.uuid, UUID);
+			assert_eq!(success.username, "player");
+		}
+		other => panic!("expected login success, got {other:?}"),
+	}
+	assert_eq!(craftflow.connections().len(), 1);
+
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+
+	assert!(matches!(
+		read_packet::<s2c::Configuration>(&mut client).await,
+		s2c::Configuration::FinishConfiguration(_)
+	));
+
+	write_packet(&mut client, &FinishConfigurationAck).await;
+
+	// closing the client side ends the connection cleanly
+	drop(client);
+	task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+	assert_eq!(disconnect_reasons(&craftflow), ["ClientClosed"]);
+}
+
+#[tokio::test]
+async fn reconfiguration() {
+	let craftflow = craftflow();
+	let (mut client, task) = connect(&craftflow);
+
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+	read_packet::<s2c::Configuration>(&mut client).await;
+	write_packet(&mut client, &FinishConfigurationAck).await;
+
+	// go back to configuration from play
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	craftflow
+		.build_packet(conn_id, |b| match b {
+			StartConfigurationBuilder::V764(p) => p(StartConfigurationV764),
+			disabled_versions!(s2c::play::StartConfigurationBuilder) => unreachable!(),
+		})
+		.await;
+	assert!(matches!(
+		read_packet::<s2c::Play>(&mut client).await,
+		s2c::Play::StartConfiguration(_)
+	));
+	write_packet(&mut client, &ConfigurationAcknowledgedV764).await;
+
+	// and finish it again
+	craftflow
+		.build_packet(conn_id, |b| match b {
+			FinishConfigurationBuilder::V764(p) => p(FinishConfigurationV764),
+			disabled_versions!(s2c::configuration::FinishConfigurationBuilder) => unreachable!(),
+		})
+		.await;
+	assert!(matches!(
+		read_packet::<s2c::Configuration>(&mut client).await,
+		s2c::Configuration::FinishConfiguration(_)
+	));
+	write_packet(&mut client, &FinishConfigurationAck).await;
+
+	drop(client);
+	task.await.unwrap().unwrap();
+
+	let changes = craftflow
+		.modules
+		.get::<StateChanges>()
+		.0
+		.lock()
+		.unwrap()
+		.clone();
+	let changes: Vec<_> = changes.iter().map(|c| (c.half, c.from, c.to)).collect();
+	// the writer always switches first, when it sends the packet that the reader waits for
+	assert_eq!(
+		changes,
+		[
+			(ConnectionHalf::Reader, State::Handshake, State::Login),
+			(ConnectionHalf::Writer, State::Handshake, State::Login),
+			(ConnectionHalf::Writer, State::Login, State::Configuration),
+			(ConnectionHalf::Reader, State::Login, State::Configuration),
+			(ConnectionHalf::Writer, State::Configuration, State::Play),
+			(ConnectionHalf::Reader, State::Configuration, State::Play),
+			(ConnectionHalf::Writer, State::Play, State::Configuration),
+			(ConnectionHalf::Reader, State::Play, State::Configuration),
+			(ConnectionHalf::Writer, State::Configuration, State::Play),
+			(ConnectionHalf::Reader, State::Configuration, State::Play),
+		]
+	);
+}
+
+#[tokio::test]
+async fn proxy_protocol() {
+	let v1 = b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 25565\r\n".to_vec();
+	let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+	v2.extend([1, 2, 3, 4, 5, 6, 7, 8, 0x04, 0xd2, 0x63, 0xdd]);
+
+	for header in [v1, v2] {
+		let craftflow = craftflow_with(Config::new().proxy_protocol([Ipv4Addr::LOCALHOST.into()]));
+		let (mut client, _task) = connect(&craftflow);
+
+		client.write_all(&header).await.unwrap();
+		login(&mut client).await;
+		read_packet::<s2c::Login>(&mut client).await;
+
+		let ip = craftflow.connections().values().next().unwrap().ip();
+		assert_eq!(ip, IpAddr::from([1, 2, 3, 4]));
+	}
+
+	// connections from untrusted addresses are not allowed to set their address
+	let craftflow =
+		craftflow_with(Config::new().proxy_protocol([Ipv4Addr::new(10, 0, 0, 1).into()]));
+	let (mut client, _task) = connect(&craftflow);
+
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+
+	let ip = craftflow.connections().values().next().unwrap().ip();
+	assert_eq!(ip, IpAddr::from(Ipv4Addr::LOCALHOST));
+}
+
+#[tokio::test]
+async fn broadcast() {
+	let craftflow = craftflow();
+	let mut clients = Vec::new();
+
+	for _ in 0..2 {
+		let (mut client, _task) = connect(&craftflow);
+
+		// wait until the connection is fully set up
+		handshake(&mut client, 1).await;
+		write_packet(&mut client, &PingStartV5).await;
+		read_packet::<s2c::Status>(&mut client).await;
+
+		clients.push(client);
+	}
+
+	craftflow
+		.broadcast(
+			|conn| conn.state() == State::Status,
+			|b| match b {
+				PingBuilder::V5(p) => p(s2c_status::ping::v5::PingV5 { time: 77 }),
+				disabled_versions!(s2c::status::PingBuilder) => unreachable!(),
+			},
+		)
+		.await;
+
+	for client in &mut clients {
+		let s2c::Status::Ping(s2c_status::Ping::V5(ping)) = read_packet(client).await else {
+			panic!("expected ping");
+		};
+		assert_eq!(ping.time, 77);
+	}
+}
+
+#[tokio::test]
+async fn broadcast_during_login() {
+	let craftflow = craftflow();
+
+	let (mut status_client, status_task) = connect(&craftflow);
+	handshake(&mut status_client, 1).await;
+	let (mut client, _task) = connect(&craftflow);
+	handshake(&mut client, 2).await;
+
+	// wait until both connections reached their states
+	while craftflow.connections().len() < 2
+		|| craftflow
+			.connections()
+			.values()
+			.any(|conn| conn.state() == State::Handshake)
+	{
+		tokio::task::yield_now().await;
+	}
+
+	// the status connection can't reach the play state and is left out,
+	// the other one gets the packet when it gets there
+	craftflow
+		.broadcast(
+			|_conn| true,
+			|b| match b {
+				KeepAliveBuilder::V5(p) => p(keep_alive::v5::KeepAliveV5 { keep_alive_id: 7 }),
+				KeepAliveBuilder::V47(p) => p(keep_alive::v47::KeepAliveV47 { keep_alive_id: 7 }),
+				KeepAliveBuilder::V340(p) => {
+					p(keep_alive::v340::KeepAliveV340 { keep_alive_id: 7 })
+				}
+				disabled_versions!(s2c::play::KeepAliveBuilder) => unreachable!(),
+			},
+		)
+		.await;
+
+	// compression is enabled during the login, after the packet was serialized
+	let conn_id = craftflow
+		.connections()
+		.values()
+		.find(|conn| conn.state() == State::Login)
+		.unwrap()
+		.id();
+	craftflow.get(conn_id).set_compression_threshold(1024);
+
+	write_small_packet(
+		&mut client,
+		&LoginStartV764 {
+			username: "player".to_owned(),
+			player_uuid: UUID,
+		},
+	)
+	.await;
+	read_small_packet::<s2c::Login>(&mut client).await;
+	write_small_packet(&mut client, &LoginAcknowledgedV764).await;
+	read_small_packet::<s2c::Configuration>(&mut client).await;
+
+	let s2c::Play::KeepAlive(s2c::play::KeepAlive::V340(keep_alive)) =
+		read_small_packet(&mut client).await
+	else {
+		panic!("expected keep alive");
+	};
+	assert_eq!(keep_alive.keep_alive_id, 7);
+
+	drop(status_client);
+	status_task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn shutdown() {
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+	let address = listener.local_addr().unwrap();
+	let craftflow = server(
+		Config::new()
+			.listener(listener)
+			.shutdown_timeout(Duration::from_millis(200)),
+	);
+	let events = Arc::new(Mutex::new(Vec::new()));
+
+	let events_ref = events.clone();
+	let _shutdown = add_runtime_callback!(craftflow.reactor, Shutdown => "shutdown" => move |_cf, reason| {
+		events_ref.lock().unwrap().push(format!("Shutdown {reason}"));
+		SmallBox::new(async { ControlFlow::Continue(()) })
+	});
+	// the first disconnect gets stuck, so the connection is dropped after the shutdown timeout
+	let events_ref = events.clone();
+	let _disconnect = add_runtime_callback!(craftflow.reactor, Disconnect => "stuck" => move |_cf, (_conn_id, reason)| {
+		let mut events = events_ref.lock().unwrap();
+		events.push(format!("Disconnect {reason:?}"));
+		let stuck = events.len() == 2;
+		SmallBox::new(async move {
+			if stuck {
+				sleep(Duration::from_secs(60)).await;
+			}
+			ControlFlow::Continue(())
+		})
+	});
+
+	let shutdown = craftflow.shutdown_handle();
+	let server = spawn(craftflow.run());
+
+	let mut client = TcpStream::connect(address).await.unwrap();
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+	read_packet::<s2c::Configuration>(&mut client).await;
+
+	shutdown.shutdown("bye");
+
+	let s2c::Play::KickDisconnect(s2c::play::KickDisconnect::V765(disconnect)) =
+		read_packet(&mut client).await
+	else {
+		panic!("expected disconnect");
+	};
+	assert_eq!(disconnect.reason, NbtValue::try_from("bye").unwrap());
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+
+	timeout(Duration::from_secs(5), server)
+		.await
+		.expect("run() did not return after the shutdown timeout")
+		.unwrap()
+		.unwrap();
+	assert_eq!(
+		*events.lock().unwrap(),
+		[
+			"Shutdown bye",
+			r#"Disconnect Shutdown("bye")"#,
+			r#"Disconnect Shutdown("bye")"#,
+		]
+	);
+}
+
+#[tokio::test]
+async fn kick() {
+	let craftflow = craftflow();
+	let (mut client, task) = connect(&craftflow);
+
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+	read_packet::<s2c::Configuration>(&mut client).await;
+
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	let reason = TextComponent::from(json!({ "text": "bye", "bold": true }));
+	craftflow.kick(conn_id, reason.clone()).await;
+
+	// the client is already in the play state, where the reason is sent as NBT
+	let s2c::Play::KickDisconnect(s2c::play::KickDisconnect::V765(disconnect)) =
+		read_packet(&mut client).await
+	else {
+		panic!("expected disconnect");
+	};
+	assert_eq!(Some(disconnect.reason), reason.to_nbt());
+
+	// and then the connection is closed
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+	task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+	assert_eq!(
+		disconnect_reasons(&craftflow),
+		[r#"Kicked({"bold":true,"text":"bye"})"#]
+	);
+}
+
+#[tokio::test]
+async fn pending_packets() {
+	let craftflow = craftflow();
+	let (mut client, _task) = connect(&craftflow);
+
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+
+	// a play packet while the client is still in configuration is held until it gets to play
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	craftflow
+		.build_packet(conn_id, |b| match b {
+			StartConfigurationBuilder::V764(p) => p(StartConfigurationV764),
+			disabled_versions!(s2c::play::StartConfigurationBuilder) => unreachable!(),
+		})
+		.await;
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+
+	assert!(matches!(
+		read_packet::<s2c::Configuration>(&mut client).await,
+		s2c::Configuration::FinishConfiguration(_)
+	));
+	assert!(matches!(
+		read_packet::<s2c::Play>(&mut client).await,
+		s2c::Play::StartConfiguration(_)
+	));
+
+	// but not forever
+	let craftflow =
+		craftflow_with(Config::new().pending_packets_timeout(Duration::from_millis(50)));
+	let (mut client, task) = connect(&craftflow);
+
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	craftflow
+		.build_packet(conn_id, |b| match b {
+			StartConfigurationBuilder::V764(p) => p(StartConfigurationV764),
+			disabled_versions!(s2c::play::StartConfigurationBuilder) => unreachable!(),
+		})
+		.await;
+
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+	task.await.unwrap().unwrap();
+	assert_eq!(disconnect_reasons(&craftflow), ["Timeout"]);
+}
+
+#[tokio::test]
+async fn virtual_hosts() {
+	let craftflow = craftflow();
+
+	let connect_to = |server_host: &str| SetProtocolV5 {
+		protocol_version: VERSION as i32,
+		server_host: server_host.to_owned(),
+		server_port: 25565,
+		next_state: 2,
+	};
+
+	// refused by the handshake event
+	let (mut client, task) = connect(&craftflow);
+	write_packet(&mut client, &connect_to("blocked.localhost")).await;
+	let s2c::Login::Disconnect(s2c::login::Disconnect::V5(disconnect)) =
+		read_packet(&mut client).await
+	else {
+		panic!("expected disconnect");
+	};
+	assert_eq!(disconnect.reason, r#"{"color":"red","text":"go away"}"#);
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+	task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+
+	// routed by the handshake event, with the extra data that mod loaders add
+	let (mut client, _task) = connect(&craftflow);
+	write_packet(&mut client, &connect_to("old.localhost.\0FML3\0")).await;
+	write_packet(
+		&mut client,
+		&LoginStartV764 {
+			username: "player".to_owned(),
+			player_uuid: UUID,
+		},
+	)
+	.await;
+	read_packet::<s2c::Login>(&mut client).await;
+
+	let conn = craftflow.connections().values().next().cloned().unwrap();
+	assert_eq!(conn.virtual_host(), "new.localhost");
+	assert_eq!(conn.handshake().server_address, "new.localhost.\0FML3\0");
+	assert_eq!(conn.handshake().server_port, 25565);
+	assert_eq!(conn.intent(), Intent::Login);
+}
+
+#[tokio::test]
+async fn unknown_packets() {
+	for strict in [false, true] {
+		let craftflow = craftflow_with(Config::new().strict_protocol(strict));
+		let (mut client, task) = connect(&craftflow);
+
+		handshake(&mut client, 1).await;
+		write_raw_packet(&mut client, 0x7e, &[1, 2, 3]).await;
+		write_raw_packet(&mut client, 0x7f, &[4]).await;
+		write_packet(&mut client, &PingV5 { time: 1234 }).await;
+
+		if strict {
+			// the second one was not handled
+			assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+			task.await.unwrap().unwrap();
+			assert!(disconnect_reasons(&craftflow)[0].starts_with("ProtocolError"));
+		} else {
+			// and otherwise they are just skipped
+			let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await
+			else {
+				panic!("expected ping");
+			};
+			assert_eq!(pong.time, 1234);
+		}
+
+		let unknown = craftflow
+			.modules
+			.get::<UnknownPackets>()
+			.0
+			.lock()
+			.unwrap()
+			.clone();
+		assert_eq!(
+			unknown,
+			[
+				RawPacket {
+					state: State::Status,
+					id: 0x7e,
+					bytes: vec![1, 2, 3],
+				},
+				RawPacket {
+					state: State::Status,
+					id: 0x7f,
+					bytes: vec![4],
+				},
+			]
+		);
+	}
+}
+
+#[tokio::test]
+async fn raw_packets() {
+	let craftflow = craftflow();
+	let (mut client, task) = connect(&craftflow);
+
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+	read_packet::<s2c::Status>(&mut client).await;
+
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	let conn = craftflow.get(conn_id);
+
+	// a known packet sent raw is the same as a normal one
+	conn.send_raw(State::Status, 0x01, 1234i64.to_be_bytes().to_vec())
+		.await;
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1234);
+
+	// and an unknown one is written as is
+	conn.send_raw(State::Status, 0x7e, vec![1, 2, 3]).await;
+	let mut packet = [0; 5];
+	client.read_exact(&mut packet).await.unwrap();
+	assert_eq!(packet, [4, 0x7e, 1, 2, 3]);
+
+	// but the state is still checked
+	conn.send_raw(State::Handshake, 0x00, Vec::new()).await;
+	drop(conn);
+
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+	task.await.unwrap().unwrap();
+	assert!(disconnect_reasons(&craftflow)[0].starts_with("ProtocolError"));
+}
+
+#[tokio::test]
+async fn traffic_stats() {
+	let craftflow = craftflow();
+	let (mut client, _task) = connect(&craftflow);
+
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+	read_packet::<s2c::Status>(&mut client).await;
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+	read_packet::<s2c::Status>(&mut client).await;
+
+	let conn_id = *craftflow.connections().keys().next().unwrap();
+	let conn = craftflow.get(conn_id);
+	let stats = conn.stats();
+
+	let packet_type = |state, id| PacketType { state, id };
+	assert_eq!(stats.packets_received(), 3);
+	assert_eq!(
+		stats
+			.packet_types_received()
+			.into_iter()
+			.collect::<Vec<_>>(),
+		[
+			(packet_type(State::Handshake, 0x00), 1),
+			(packet_type(State::Status, 0x00), 1),
+			(packet_type(State::Status, 0x01), 1),
+		]
+	);
+	assert_eq!(stats.packets_sent(), 2);
+	assert_eq!(
+		stats.packet_types_sent().into_iter().collect::<Vec<_>>(),
+		[
+			(packet_type(State::Status, 0x00), 1),
+			(packet_type(State::Status, 0x01), 1),
+		]
+	);
+
+	// without compression the only difference is the length prefixes
+	assert_eq!(
+		stats.bytes_received(),
+		stats.bytes_received_decompressed() + 3
+	);
+	assert_eq!(stats.bytes_sent(), stats.bytes_sent_uncompressed() + 2);
+	// the ping packet is the ID and an i64
+	assert!(stats.bytes_sent_uncompressed() > 9);
+
+	assert!(stats.last_received() > stats.started());
+	assert!(stats.last_sent() >= stats.last_received());
+}
+
+#[tokio::test]
+async fn any_packet_events() {
+	let craftflow = craftflow();
+	let received = Arc::new(Mutex::new(Vec::new()));
+	let sent = Arc::new(Mutex::new(Vec::new()));
+
+	let received_ref = received.clone();
+	let _received = add_runtime_callback!(craftflow.reactor, AnyPacket<C2S> => "any_c2s" => move |_cf, (_conn_id, view)| {
+		assert_eq!(view.protocol_version, VERSION);
+		received_ref.lock().unwrap().push((view.state, view.name));
+		SmallBox::new(async { ControlFlow::Continue(()) })
+	});
+	let sent_ref = sent.clone();
+	let _sent = add_runtime_callback!(craftflow.reactor, Post<AnyPacket<s2c::Status>> => "any_s2c" => move |_cf, (_conn_id, view)| {
+		sent_ref.lock().unwrap().push(format!("{} {:?}", view.name, view.packet));
+		SmallBox::new(async { ControlFlow::Continue(()) })
+	});
+	// stops the first ping before the specific packet events
+	let stopped = AtomicBool::new(false);
+	let _stop = add_runtime_callback!(craftflow.reactor, AnyPacket<c2s::Status> => "stop" => move |_cf, (_conn_id, view)| {
+		let stop = view.name == "Ping" && !stopped.swap(true, Ordering::Relaxed);
+		SmallBox::new(async move {
+			match stop {
+				true => ControlFlow::Break(()),
+				false => ControlFlow::Continue(()),
+			}
+		})
+	});
+
+	let (mut client, _task) = connect(&craftflow);
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+	read_packet::<s2c::Status>(&mut client).await;
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+	write_packet(&mut client, &PingV5 { time: 1235 }).await;
+
+	// the first ping was stopped, so the first pong is for the second one
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1235);
+
+	assert_eq!(
+		*received.lock().unwrap(),
+		[
+			(State::Handshake, "SetProtocol"),
+			(State::Status, "PingStart"),
+			(State::Status, "Ping"),
+			(State::Status, "Ping"),
+		]
+	);
+	// the post-send event of the pong might not be triggered yet, but the server info's was
+	let sent = sent.lock().unwrap();
+	assert!(sent[0].starts_with("ServerInfo ServerInfoV5"));
+}
+
+#[tokio::test]
+async fn callback_panics() {
+	let craftflow = craftflow_with(Config::new().callback_panic_policy(PanicPolicy::Skip));
+	let _panic = add_runtime_callback!(craftflow.reactor, Packet<Ping> => "panic" => |_cf, _args| SmallBox::new(async {
+			panic!("buggy module");
+		}), before: "in_memory:pong");
+
+	let (mut client, task) = connect(&craftflow);
+	handshake(&mut client, 1).await;
+	write_packet(&mut client, &PingStartV5).await;
+	read_packet::<s2c::Status>(&mut client).await;
+
+	// the connection survives and the other callbacks still run
+	write_packet(&mut client, &PingV5 { time: 1234 }).await;
+	let s2c::Status::Ping(s2c_status::Ping::V5(pong)) = read_packet(&mut client).await else {
+		panic!("expected ping");
+	};
+	assert_eq!(pong.time, 1234);
+
+	drop(client);
+	task.await.unwrap().unwrap();
+}
@@ -1,5 +1,7 @@
+use connection_limits::{ConnectionLimits, DEFAULT_MAX_PLAYERS};
 use craftflow::{CraftFlow, add_callback, packet_events::Packet};
 use craftflow_protocol::c2s::login::LoginStart;
+use keepalive::KeepAlive;
 use login::Login;
 use simple_ping::SimplePing;
 use smallbox::SmallBox;
@@ -27,12 +29,17 @@ async fn main() -> anyhow::Result<()> {
 			color = "white",
 			bold
 		))
+		.set_max_players(DEFAULT_MAX_PLAYERS)
 		.register(&mut craftflow);
 
 	Login::default().register(&mut craftflow);
 
 	World::new().register(&mut craftflow);
 
+	KeepAlive::default().register(&mut craftflow);
+
+	ConnectionLimits::default().register(&mut craftflow);
+
 	add_callback!(craftflow.reactor, Packet<LoginStart> => "printer" => |cf, (conn_id, packet)| SmallBox::new(async move {
 		println!("{} {:?}", conn_id, packet);
 
@@ -43,6 +50,14 @@ async fn main() -> anyhow::Result<()> {
 		std::ops::ControlFlow::Continue(())
 	}));
 
+	// shut down gracefully on Ctrl+C
+	let shutdown = craftflow.shutdown_handle();
+	tokio::spawn(async move {
+		if tokio::signal::ctrl_c().await.is_ok() {
+			shutdown.shutdown("Server closed");
+		}
+	});
+
 	info!("Starting CraftFlow");
 
 	craftflow.run().await
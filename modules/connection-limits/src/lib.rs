@@ -0,0 +1,127 @@
+#![doc(
+	html_favicon_url = "https://github.com/PonasKovas/craftflow/blob/master/assets/icon.png?raw=true"
+)]
+#![doc(
+	html_logo_url = "https://github.com/PonasKovas/craftflow/blob/master/assets/icon.png?raw=true"
+)]
+
+mod login_start;
+mod new_connection;
+mod rate_limit;
+
+use craftflow::CraftFlow;
+use rate_limit::RateLimiter;
+use std::{net::IpAddr, time::Duration};
+
+craftflow::init!();
+
+/// The default maximum number of players on the server
+pub const DEFAULT_MAX_PLAYERS: usize = 100;
+/// The default maximum number of connections from a single IP address at once
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 8;
+/// The default number of new connections per second allowed from a single subnet
+pub const DEFAULT_HANDSHAKE_RATE: f64 = 2.0;
+/// The default number of new connections allowed from a single subnet in a quick burst
+pub const DEFAULT_HANDSHAKE_BURST: u32 = 8;
+/// The default time that a subnet is not allowed to connect after exceeding the rate limit
+pub const DEFAULT_THROTTLE_DURATION: Duration = Duration::from_secs(30);
+
+/// A module that limits the number of players and connections, and the rate of new connections:
+/// - Players are kicked when logging in if the server is full
+/// - Connections are closed right away if there are too many from the same IP address
+/// - Connections are closed right away if a subnet connects too often, and it is throttled
+///   (not allowed to connect at all) for a while. Up to 65536 subnets that connected recently
+///   are tracked, new subnets are not allowed to connect while there are more.
+pub struct ConnectionLimits {
+	max_players: usize,
+	max_connections_per_ip: usize,
+	server_full_message: String,
+	rate_limiter: RateLimiter,
+}
+
+impl ConnectionLimits {
+	/// Creates a new ConnectionLimits module instance with the default limits
+	pub fn new() -> Self {
+		Self {
+			max_players: DEFAULT_MAX_PLAYERS,
+			max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+			server_full_message: "The server is full!".to_owned(),
+			rate_limiter: RateLimiter::new(
+				DEFAULT_HANDSHAKE_RATE,
+				DEFAULT_HANDSHAKE_BURST,
+				DEFAULT_THROTTLE_DURATION,
+			),
+		}
+	}
+	/// Sets the maximum number of players. Default is [`DEFAULT_MAX_PLAYERS`].
+	///
+	/// All connections that are not in the Status state count as players, including the ones
+	/// that are still logging in. The max players shown in the server list are set separately,
+	/// for example with `SimplePing::set_max_players`.
+	pub fn set_max_players(mut self, max_players: usize) -> Self {
+		self.max_players = max_players;
+		self
+	}
+	/// Sets the maximum number of connections from a single IP address at once.
+	/// Default is [`DEFAULT_MAX_CONNECTIONS_PER_IP`].
+	///
+	/// Only the connections that finished the handshake count, the ones that are still sending
+	/// it (which is limited to 5 seconds) or doing a legacy ping don't. Those are only limited by
+	/// the handshake rate, see [`ConnectionLimits::set_handshake_rate`].
+	pub fn set_max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+		self.max_connections_per_ip = max_connections_per_ip;
+		self
+	}
+	/// Sets the message that players see when they are kicked because the server is full
+	pub fn set_server_full_message(mut self, message: impl Into<String>) -> Self {
+		self.server_full_message = message.into();
+		self
+	}
+	/// Sets how many new connections per second are allowed from a single subnet, and how many
+	/// in a quick burst. Defaults are [`DEFAULT_HANDSHAKE_RATE`] and [`DEFAULT_HANDSHAKE_BURST`].
+	pub fn set_handshake_rate(mut self, per_second: f64, burst: u32) -> Self {
+		self.rate_limiter.rate = per_second;
+		self.rate_limiter.burst = burst;
+		self
+	}
+	/// Sets the prefix lengths of IPv4 and IPv6 subnets that share the same rate limit.
+	///
+	/// Default is 32 for IPv4 (each address separately) and 64 for IPv6, since a single
+	/// client usually has a whole /64.
+	pub fn set_subnet_prefixes(mut self, ipv4: u8, ipv6: u8) -> Self {
+		assert!(ipv4 <= 32, "invalid IPv4 prefix length");
+		assert!(ipv6 <= 128, "invalid IPv6 prefix length");
+
+		self.rate_limiter.ipv4_prefix = ipv4;
+		self.rate_limiter.ipv6_prefix = ipv6;
+		self
+	}
+	/// Sets how long a subnet is not allowed to connect after exceeding the rate limit.
+	/// Default is [`DEFAULT_THROTTLE_DURATION`].
+	pub fn set_throttle_duration(mut self, duration: Duration) -> Self {
+		self.rate_limiter.throttle_duration = duration;
+		self
+	}
+
+	/// Adds the module to a CraftFlow instance.
+	pub fn register(self, craftflow: &mut CraftFlow) {
+		craftflow.modules.register(self);
+
+		craftflow::reg!(to: &mut craftflow.reactor);
+	}
+
+	/// Returns the maximum number of players
+	pub fn max_players(&self) -> usize {
+		self.max_players
+	}
+	/// Doesn't allow the subnet of the given IP address to connect for the given duration
+	pub fn throttle(&self, ip: IpAddr, duration: Duration) {
+		self.rate_limiter.throttle(ip, duration);
+	}
+}
+
+impl Default for ConnectionLimits {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,27 @@
+use crate::ConnectionLimits;
+use craftflow::{ConnId, CraftFlow, connection::State, packet_events::Packet};
+use craftflow_protocol::c2s::login::LoginStart;
+use std::{ops::ControlFlow, sync::Arc};
+
+// must come before the login module starts logging the player in
+#[craftflow::callback(event: Packet<LoginStart>, before: "login:login_start")]
+pub async fn login_start(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, _): &mut (ConnId, LoginStart),
+) -> ControlFlow<()> {
+	let limits = cf.modules.get::<ConnectionLimits>();
+
+	// everyone who is not just pinging the server counts as a player, including the connections
+	// that are still logging in (like this one), so that simultaneous logins can't overfill it
+	let players = cf
+		.connections()
+		.values()
+		.filter(|conn| conn.state() != State::Status)
+		.count();
+	if players > limits.max_players {
+		cf.kick(conn_id, limits.server_full_message.clone()).await;
+		return ControlFlow::Break(());
+	}
+
+	ControlFlow::Continue(())
+}
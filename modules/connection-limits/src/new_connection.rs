@@ -0,0 +1,27 @@
+use crate::ConnectionLimits;
+use craftflow::{CraftFlow, various_events::NewConnection};
+use std::{net::IpAddr, ops::ControlFlow, sync::Arc};
+use tracing::debug;
+
+#[craftflow::callback(event: NewConnection)]
+pub async fn new_connection(cf: &Arc<CraftFlow>, &mut ip: &mut IpAddr) -> ControlFlow<()> {
+	let limits = cf.modules.get::<ConnectionLimits>();
+
+	if !limits.rate_limiter.allow(ip) {
+		debug!("{ip} is connecting too often");
+		return ControlFlow::Break(());
+	}
+
+	// connections are only added to the list after the handshake, so this one isn't there yet
+	let connections = cf
+		.connections()
+		.values()
+		.filter(|conn| conn.ip() == ip)
+		.count();
+	if connections >= limits.max_connections_per_ip {
+		debug!("{ip} has too many connections");
+		return ControlFlow::Break(());
+	}
+
+	ControlFlow::Continue(())
+}
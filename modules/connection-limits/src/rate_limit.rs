@@ -0,0 +1,262 @@
+use std::{
+	collections::HashMap,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr},
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+// idle buckets are first cleaned up when there are this many, and then each time the number
+// of buckets doubles since the last cleanup, so that it doesn't run on every connection
+const CLEANUP_THRESHOLD: usize = 4096;
+// new subnets are refused while there are this many buckets that can't be cleaned up
+const MAX_BUCKETS: usize = 65536;
+// how often the buckets are cleaned up while there are too many of them
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token bucket rate limiter of new connections per subnet
+pub(crate) struct RateLimiter {
+	pub(crate) rate: f64,
+	pub(crate) burst: u32,
+	pub(crate) throttle_duration: Duration,
+	pub(crate) ipv4_prefix: u8,
+	pub(crate) ipv6_prefix: u8,
+	buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+	map: HashMap<IpAddr, Bucket>,
+	cleanup_at: usize,
+	cleaned: Option<Instant>,
+}
+
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+	throttled_until: Option<Instant>,
+}
+
+impl RateLimiter {
+	pub(crate) fn new(rate: f64, burst: u32, throttle_duration: Duration) -> Self {
+		Self {
+			rate,
+			burst,
+			throttle_duration,
+			ipv4_prefix: 32,
+			ipv6_prefix: 64,
+			buckets: Mutex::new(Buckets {
+				map: HashMap::new(),
+				cleanup_at: CLEANUP_THRESHOLD,
+				cleaned: None,
+			}),
+		}
+	}
+	/// Takes a token from the bucket of the subnet of the IP address.
+	///
+	/// Returns false if the connection must not be allowed, because the subnet is throttled or
+	/// there are no tokens left. In the latter case the subnet is throttled.
+	/// New subnets are also not allowed while too many others are being tracked.
+	pub(crate) fn allow(&self, ip: IpAddr) -> bool {
+		self.allow_at(ip, Instant::now())
+	}
+	fn allow_at(&self, ip: IpAddr, now: Instant) -> bool {
+		let mut buckets = self.buckets.lock().unwrap();
+
+		let subnet = self.subnet(ip);
+		if !buckets.map.contains_key(&subnet) && !self.make_room(&mut buckets, now) {
+			return false;
+		}
+
+		let bucket = buckets.map.entry(subnet).or_insert(Bucket {
+			tokens: self.burst as f64,
+			updated: now,
+			throttled_until: None,
+		});
+
+		if bucket.throttled_until.is_some_and(|until| until > now) {
+			return false;
+		}
+		bucket.throttled_until = None;
+
+		self.refill(bucket, now);
+		if bucket.tokens < 1.0 {
+			bucket.throttled_until = Some(now + self.throttle_duration);
+			return false;
+		}
+		bucket.tokens -= 1.0;
+
+		true
+	}
+	/// Doesn't allow the subnet of the IP address to connect for the given duration
+	pub(crate) fn throttle(&self, ip: IpAddr, duration: Duration) {
+		let now = Instant::now();
+		let mut buckets = self.buckets.lock().unwrap();
+
+		let bucket = buckets.map.entry(self.subnet(ip)).or_insert(Bucket {
+			tokens: self.burst as f64,
+			updated: now,
+			throttled_until: None,
+		});
+		bucket.throttled_until = Some(now + duration);
+	}
+	// Cleans up the idle buckets if it's time to, returning false if there's no room for a new one
+	fn make_room(&self, buckets: &mut Buckets, now: Instant) -> bool {
+		let full = buckets.map.len() >= MAX_BUCKETS;
+		let can_clean = buckets
+			.cleaned
+			.is_none_or(|cleaned| now >= cleaned + CLEANUP_INTERVAL);
+
+		if buckets.map.len() >= buckets.cleanup_at || (full && can_clean) {
+			buckets.map.retain(|_, bucket| !self.is_idle(bucket, now));
+			buckets.cleanup_at = (buckets.map.len() * 2).max(CLEANUP_THRESHOLD);
+			buckets.cleaned = Some(now);
+		}
+
+		buckets.map.len() < MAX_BUCKETS
+	}
+	fn refill(&self, bucket: &mut Bucket, now: Instant) {
+		let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst as f64);
+		bucket.updated = now;
+	}
+	// a bucket that is full and not throttled is the same as no bucket at all
+	fn is_idle(&self, bucket: &Bucket, now: Instant) -> bool {
+		let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+
+		bucket.throttled_until.is_none_or(|until| until <= now)
+			&& bucket.tokens + elapsed * self.rate >= self.burst as f64
+	}
+	/// Masks the IP address to the configured subnet prefix length
+	fn subnet(&self, ip: IpAddr) -> IpAddr {
+		match ip.to_canonical() {
+			IpAddr::V4(ip) => {
+				let mask = u32::MAX.checked_shl(32 - self.ipv4_prefix as u32);
+				Ipv4Addr::from(u32::from(ip) & mask.unwrap_or(0)).into()
+			}
+			IpAddr::V6(ip) => {
+				let mask = u128::MAX.checked_shl(128 - self.ipv6_prefix as u32);
+				Ipv6Addr::from(u128::from(ip) & mask.unwrap_or(0)).into()
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const A: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+	const B: IpAddr = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+
+	fn secs(secs: u64) -> Duration {
+		Duration::from_secs(secs)
+	}
+
+	#[test]
+	fn test_token_bucket() {
+		let limiter = RateLimiter::new(1.0, 2, secs(10));
+		let start = Instant::now();
+
+		assert!(limiter.allow_at(A, start));
+		assert!(limiter.allow_at(A, start));
+		// out of tokens, so throttled
+		assert!(!limiter.allow_at(A, start));
+		assert!(limiter.allow_at(B, start));
+
+		// still throttled even though the tokens were refilled
+		assert!(!limiter.allow_at(A, start + secs(5)));
+		assert!(limiter.allow_at(A, start + secs(10)));
+		assert!(limiter.allow_at(A, start + secs(10)));
+		assert!(!limiter.allow_at(A, start + secs(10)));
+
+		// one token per second
+		assert!(limiter.allow_at(B, start + secs(1)));
+		assert!(limiter.allow_at(B, start + secs(2)));
+		assert!(limiter.allow_at(B, start + secs(3)));
+	}
+
+	#[test]
+	fn test_subnet() {
+		let mut limiter = RateLimiter::new(1.0, 1, secs(10));
+		let v6: IpAddr = "2001:db8::1".parse().unwrap();
+		let mapped: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+
+		assert_eq!(limiter.subnet(A), A);
+		assert_eq!(limiter.subnet(v6), "2001:db8::".parse::<IpAddr>().unwrap());
+		// IPv4-mapped IPv6 addresses are limited as IPv4 ones
+		assert_eq!(limiter.subnet(mapped), A);
+
+		limiter.ipv4_prefix = 24;
+		assert_eq!(limiter.subnet(mapped), "1.2.3.0".parse::<IpAddr>().unwrap());
+
+		limiter.ipv4_prefix = 0;
+		limiter.ipv6_prefix = 0;
+		assert_eq!(limiter.subnet(A), IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+		assert_eq!(limiter.subnet(v6), IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+
+		limiter.ipv6_prefix = 128;
+		assert_eq!(limiter.subnet(v6), v6);
+	}
+
+	#[test]
+	fn test_cleanup() {
+		let limiter = RateLimiter::new(1.0, 2, secs(10));
+		let start = Instant::now();
+		let len = || limiter.buckets.lock().unwrap().map.len();
+
+		for i in 0..CLEANUP_THRESHOLD as u32 {
+			assert!(limiter.allow_at(Ipv4Addr::from(i).into(), start));
+		}
+		limiter.throttle(A, secs(60));
+		assert_eq!(len(), CLEANUP_THRESHOLD + 1);
+
+		// the buckets are full again, so they are removed, except the throttled one
+		assert!(limiter.allow_at(B, start + secs(1)));
+		assert_eq!(len(), 2);
+		assert!(!limiter.allow(A));
+	}
+
+	#[test]
+	fn test_cleanup_threshold() {
+		let limiter = RateLimiter::new(1.0, 2, secs(10));
+		let start = Instant::now();
+
+		// none of the buckets can be removed yet, so the next cleanup is postponed
+		for i in 0..=CLEANUP_THRESHOLD as u32 {
+			assert!(limiter.allow_at(Ipv4Addr::from(i).into(), start));
+		}
+		let buckets = limiter.buckets.lock().unwrap();
+		assert_eq!(buckets.map.len(), CLEANUP_THRESHOLD + 1);
+		assert_eq!(buckets.cleanup_at, CLEANUP_THRESHOLD * 2);
+	}
+
+	#[test]
+	fn test_max_buckets() {
+		let limiter = RateLimiter::new(1.0, 2, secs(10));
+		let start = Instant::now();
+
+		for i in 0..MAX_BUCKETS as u32 {
+			assert!(limiter.allow_at(Ipv4Addr::from(i).into(), start));
+		}
+
+		// new subnets are refused, but the known ones can still connect
+		assert!(!limiter.allow_at(A, start));
+		assert!(!limiter.allow_at(A, start + secs(1) / 2));
+		assert!(limiter.allow_at(Ipv4Addr::from(0).into(), start));
+
+		// until the buckets can be cleaned up
+		assert!(limiter.allow_at(A, start + secs(2)));
+		assert_eq!(limiter.buckets.lock().unwrap().map.len(), 1);
+	}
+
+	#[test]
+	fn test_throttle() {
+		let limiter = RateLimiter::new(1.0, 2, secs(10));
+
+		limiter.throttle(A, secs(60));
+		assert!(!limiter.allow(A));
+		assert!(limiter.allow(B));
+
+		limiter.throttle(A, Duration::ZERO);
+		assert!(limiter.allow(A));
+	}
+}
@@ -0,0 +1,97 @@
+//! Drives connections from different addresses over in-memory streams
+
+use connection_limits::ConnectionLimits;
+use craftflow::CraftFlow;
+use craftflow_protocol::s2c;
+use craftflow_test_support::{connect_from, handshake, login, read_packet};
+use std::{
+	net::{IpAddr, Ipv4Addr},
+	sync::Arc,
+	time::Duration,
+};
+use tokio::{
+	io::AsyncReadExt,
+	time::{sleep, timeout},
+};
+
+const A: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+const B: IpAddr = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+const C: IpAddr = IpAddr::V4(Ipv4Addr::new(9, 10, 11, 12));
+
+fn craftflow(limits: ConnectionLimits) -> Arc<CraftFlow> {
+	let mut craftflow = CraftFlow::new();
+	limits.register(&mut craftflow);
+	// logs the players in after the connection limits checked them, since it's registered later
+	craftflow_test_support::register(&mut craftflow);
+
+	Arc::new(craftflow)
+}
+
+/// Waits until the given number of connections are in the connection list
+async fn wait_for_connections(craftflow: &CraftFlow, n: usize) {
+	timeout(Duration::from_secs(5), async {
+		while craftflow.connections().len() != n {
+			sleep(Duration::from_millis(10)).await;
+		}
+	})
+	.await
+	.unwrap();
+}
+
+#[tokio::test]
+async fn max_connections_per_ip() {
+	let craftflow = craftflow(ConnectionLimits::new().set_max_connections_per_ip(1));
+
+	let (mut first, first_task) = connect_from(&craftflow, A);
+	handshake(&mut first, 1).await;
+	wait_for_connections(&craftflow, 1).await;
+
+	// another connection from the same address is closed right away
+	let (mut second, second_task) = connect_from(&craftflow, A);
+	assert_eq!(second.read(&mut [0]).await.unwrap(), 0);
+	second_task.await.unwrap().unwrap();
+
+	// but other addresses can still connect
+	let (mut third, third_task) = connect_from(&craftflow, B);
+	handshake(&mut third, 1).await;
+	wait_for_connections(&craftflow, 2).await;
+
+	drop((first, third));
+	first_task.await.unwrap().unwrap();
+	third_task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+}
+
+#[tokio::test]
+async fn max_players() {
+	let craftflow = craftflow(ConnectionLimits::new().set_max_players(1));
+
+	// pinging the server doesn't count as a player
+	let (mut pinging, pinging_task) = connect_from(&craftflow, A);
+	handshake(&mut pinging, 1).await;
+	wait_for_connections(&craftflow, 1).await;
+
+	let (mut player, player_task) = connect_from(&craftflow, B);
+	login(&mut player).await;
+	assert!(matches!(
+		read_packet::<s2c::Login>(&mut player).await,
+		s2c::Login::Success(_)
+	));
+
+	// but now the server is full
+	let (mut second, second_task) = connect_from(&craftflow, C);
+	login(&mut second).await;
+	let s2c::Login::Disconnect(s2c::login::Disconnect::V5(disconnect)) =
+		read_packet(&mut second).await
+	else {
+		panic!("expected disconnect");
+	};
+	assert_eq!(disconnect.reason, r#""The server is full!""#);
+	assert_eq!(second.read(&mut [0]).await.unwrap(), 0);
+	second_task.await.unwrap().unwrap();
+
+	drop((pinging, player));
+	pinging_task.await.unwrap().unwrap();
+	player_task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+}
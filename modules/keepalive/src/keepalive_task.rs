@@ -0,0 +1,91 @@
+use crate::KeepAliveState;
+use craftflow::{ConnId, CraftFlow, connection::State};
+use craftflow_protocol::{
+	disabled_versions,
+	s2c::{
+		configuration,
+		play::{self, keep_alive::*},
+	},
+};
+use std::{
+	sync::{Arc, Weak},
+	time::Duration,
+};
+use tokio::time::{Instant, sleep_until};
+use tracing::debug;
+
+/// Sends keep-alives to a connection every `interval` and kicks it if it doesn't answer
+/// in `timeout`. Ends when the connection is closed.
+pub(crate) async fn keepalive_task(
+	cf: Weak<CraftFlow>,
+	conn_id: ConnId,
+	state: Arc<KeepAliveState>,
+	interval: Duration,
+	timeout: Duration,
+) {
+	let mut next_send = Instant::now() + interval;
+
+	loop {
+		let wake_up = match state.pending_since() {
+			Some(sent) => next_send.min(sent + timeout),
+			None => next_send,
+		};
+		sleep_until(wake_up).await;
+
+		let Some(cf) = cf.upgrade() else {
+			return;
+		};
+		let Some(conn) = cf.connections().get(&conn_id).cloned() else {
+			return;
+		};
+
+		let now = Instant::now();
+		if state
+			.pending_since()
+			.is_some_and(|sent| sent + timeout <= now)
+		{
+			debug!("{conn_id} didn't answer a keep-alive in time");
+			cf.kick(conn_id, "Timed out").await;
+			return;
+		}
+
+		if now < next_send {
+			continue;
+		}
+		next_send = now + interval;
+
+		// only one keep-alive can be unanswered at a time
+		if state.pending_since().is_some() {
+			continue;
+		}
+
+		match conn.state() {
+			State::Configuration => {
+				let keep_alive_id = state.send();
+				cf.build_packet(conn_id, |b| match b {
+					configuration::KeepAliveBuilder::V764(p) => {
+						p(configuration::keep_alive::v764::KeepAliveV764 { keep_alive_id })
+					}
+					disabled_versions!(s2c::configuration::KeepAliveBuilder) => unreachable!(),
+				})
+				.await;
+			}
+			State::Play => {
+				let keep_alive_id = state.send();
+				cf.build_packet(conn_id, |b| match b {
+					play::KeepAliveBuilder::V5(p) => p(v5::KeepAliveV5 {
+						keep_alive_id: keep_alive_id as i32,
+					}),
+					play::KeepAliveBuilder::V47(p) => p(v47::KeepAliveV47 {
+						keep_alive_id: keep_alive_id as i32,
+					}),
+					play::KeepAliveBuilder::V340(p) => p(v340::KeepAliveV340 { keep_alive_id }),
+					disabled_versions!(s2c::play::KeepAliveBuilder) => unreachable!(),
+				})
+				.await;
+			}
+			// back in an earlier state, nothing to do
+			_ => {}
+		}
+	}
+}
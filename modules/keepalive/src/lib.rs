@@ -0,0 +1,208 @@
+#![doc(
+	html_favicon_url = "https://github.com/PonasKovas/craftflow/blob/master/assets/icon.png?raw=true"
+)]
+#![doc(
+	html_logo_url = "https://github.com/PonasKovas/craftflow/blob/master/assets/icon.png?raw=true"
+)]
+
+mod keepalive_task;
+mod responses;
+
+use craftflow::{
+	ConnId, CraftFlow, callback,
+	connection::State,
+	various_events::{ConnectionHalf, StateChange, StateChanged},
+};
+use keepalive_task::keepalive_task;
+use std::{
+	ops::ControlFlow,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use tokio::{spawn, time::Instant};
+
+craftflow::init!();
+
+/// The default time between keep-alive packets
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+/// The default time a client has to answer a keep-alive packet
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A module that sends keep-alive packets to clients in the Configuration and Play states,
+/// kicks the ones that don't answer in time and measures their latency
+pub struct KeepAlive {
+	interval: Duration,
+	timeout: Duration,
+}
+
+/// The keep-alive state of a connection.
+///
+/// Attached to the connection once it reaches the Configuration or Play state,
+/// see [`ConnectionInterface::attachments`][craftflow::connection::ConnectionInterface::attachments].
+pub struct KeepAliveState {
+	inner: Mutex<StateInner>,
+}
+
+struct StateInner {
+	next_id: i32,
+	// the keep-alive that the client hasn't answered yet, with the time it was sent
+	pending: Option<(i64, Instant)>,
+	latency: Option<Duration>,
+}
+
+impl KeepAlive {
+	/// Creates a new KeepAlive module instance with the default interval and timeout
+	pub fn new() -> Self {
+		Self {
+			interval: DEFAULT_INTERVAL,
+			timeout: DEFAULT_TIMEOUT,
+		}
+	}
+	/// Sets the time between keep-alive packets. Default is [`DEFAULT_INTERVAL`].
+	pub fn interval(mut self, interval: Duration) -> Self {
+		self.interval = interval;
+		self
+	}
+	/// Sets how long a client has to answer a keep-alive packet before it is kicked.
+	/// Default is [`DEFAULT_TIMEOUT`].
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Adds the module to a CraftFlow instance.
+	pub fn register(self, craftflow: &mut CraftFlow) {
+		craftflow.modules.register(self);
+
+		craftflow::reg!(to: &mut craftflow.reactor);
+	}
+}
+
+impl Default for KeepAlive {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl KeepAliveState {
+	fn new() -> Self {
+		Self {
+			inner: Mutex::new(StateInner {
+				next_id: 0,
+				pending: None,
+				latency: None,
+			}),
+		}
+	}
+	/// The smoothed round-trip time of keep-alive packets.
+	/// `None` until the client answers the first one.
+	pub fn latency(&self) -> Option<Duration> {
+		self.inner.lock().unwrap().latency
+	}
+	/// Marks a new keep-alive as sent, returning its ID
+	fn send(&self) -> i64 {
+		let mut inner = self.inner.lock().unwrap();
+
+		// small enough for the older versions, which use 32 bit IDs
+		let id = inner.next_id as i64;
+		inner.next_id = inner.next_id.wrapping_add(1);
+		inner.pending = Some((id, Instant::now()));
+
+		id
+	}
+	/// The time when the unanswered keep-alive was sent, if there is one
+	fn pending_since(&self) -> Option<Instant> {
+		self.inner.lock().unwrap().pending.map(|(_, sent)| sent)
+	}
+	/// Handles the client's answer. Returns false if it was not expected
+	fn answer(&self, id: i64) -> bool {
+		let mut inner = self.inner.lock().unwrap();
+
+		let Some((_, sent)) = inner.pending.filter(|&(pending_id, _)| pending_id == id) else {
+			return false;
+		};
+		inner.pending = None;
+
+		// smoothed the same way as TCP does it
+		let sample = sent.elapsed();
+		inner.latency = Some(match inner.latency {
+			Some(latency) => (latency * 7 + sample) / 8,
+			None => sample,
+		});
+
+		true
+	}
+}
+
+#[callback(event: StateChanged)]
+async fn state_changed(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, change): &mut (ConnId, StateChange),
+) -> ControlFlow<()> {
+	if change.half != ConnectionHalf::Writer
+		|| !matches!(change.to, State::Configuration | State::Play)
+	{
+		return ControlFlow::Continue(());
+	}
+	let Some(conn) = cf.connections().get(&conn_id).cloned() else {
+		return ControlFlow::Continue(());
+	};
+
+	// start sending keep-alives the first time the connection gets to one of the states
+	let mut started = false;
+	let state = conn.attachments().get_or_insert_with(|| {
+		started = true;
+		KeepAliveState::new()
+	});
+	if started {
+		let module = cf.modules.get::<KeepAlive>();
+		spawn(keepalive_task(
+			Arc::downgrade(cf),
+			conn_id,
+			state,
+			module.interval,
+			module.timeout,
+		));
+	}
+
+	ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_answer() {
+		let state = KeepAliveState::new();
+
+		// nothing was sent yet
+		assert!(!state.answer(0));
+
+		let id = state.send();
+		assert!(state.pending_since().is_some());
+		assert!(!state.answer(id + 1));
+		assert!(state.pending_since().is_some());
+		assert!(state.answer(id));
+		assert!(state.pending_since().is_none());
+		assert!(state.latency().is_some());
+
+		// it can't be answered twice
+		assert!(!state.answer(id));
+		assert_ne!(state.send(), id);
+	}
+
+	#[test]
+	fn test_latency_smoothing() {
+		let state = KeepAliveState::new();
+		state.inner.lock().unwrap().latency = Some(Duration::from_millis(800));
+
+		let id = state.send();
+		assert!(state.answer(id));
+
+		// a fast answer only moves the latency an eighth of the way
+		let latency = state.latency().unwrap();
+		assert!(latency >= Duration::from_millis(700));
+		assert!(latency < Duration::from_millis(710));
+	}
+}
@@ -0,0 +1,49 @@
+use crate::KeepAliveState;
+use craftflow::{ConnId, CraftFlow, callback, packet_events::Packet};
+use craftflow_protocol::{
+	c2s::{configuration, play},
+	disabled_versions,
+};
+use std::{ops::ControlFlow, sync::Arc};
+
+#[callback(event: Packet<configuration::KeepAlive>)]
+async fn configuration_keep_alive(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, ref mut packet): &mut (ConnId, configuration::KeepAlive),
+) -> ControlFlow<()> {
+	let id = match packet {
+		configuration::KeepAlive::V764(p) => p.keep_alive_id,
+		disabled_versions!(c2s::configuration::KeepAlive) => unreachable!(),
+	};
+	answer(cf, conn_id, id).await;
+
+	ControlFlow::Continue(())
+}
+
+#[callback(event: Packet<play::KeepAlive>)]
+async fn play_keep_alive(
+	cf: &Arc<CraftFlow>,
+	&mut (conn_id, ref mut packet): &mut (ConnId, play::KeepAlive),
+) -> ControlFlow<()> {
+	let id = match packet {
+		play::KeepAlive::V5(p) => p.keep_alive_id as i64,
+		play::KeepAlive::V47(p) => p.keep_alive_id as i64,
+		play::KeepAlive::V340(p) => p.keep_alive_id,
+		disabled_versions!(c2s::play::KeepAlive) => unreachable!(),
+	};
+	answer(cf, conn_id, id).await;
+
+	ControlFlow::Continue(())
+}
+
+async fn answer(cf: &Arc<CraftFlow>, conn_id: ConnId, id: i64) {
+	let Some(conn) = cf.connections().get(&conn_id).cloned() else {
+		return;
+	};
+	let state = conn.attachments().get::<KeepAliveState>();
+
+	// the client can only echo the keep-alive that it was sent
+	if !state.is_some_and(|state| state.answer(id)) {
+		cf.kick(conn_id, "Invalid keep-alive").await;
+	}
+}
@@ -0,0 +1,70 @@
+//! Drives a connection over an in-memory stream with a short keep-alive interval and timeout
+
+use craftflow::CraftFlow;
+use craftflow_protocol::{
+	c2s::{self, login::login_acknowledged::v764::LoginAcknowledgedV764},
+	craftflow_nbt::NbtValue,
+	s2c,
+};
+use craftflow_test_support::{connect, login, read_packet, write_packet};
+use keepalive::{KeepAlive, KeepAliveState};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt},
+	time::{sleep, timeout},
+};
+
+async fn read_keep_alive(client: &mut (impl AsyncRead + Unpin)) -> i64 {
+	let s2c::Configuration::KeepAlive(s2c::configuration::KeepAlive::V764(keep_alive)) =
+		read_packet(client).await
+	else {
+		panic!("expected keep-alive");
+	};
+
+	keep_alive.keep_alive_id
+}
+
+#[tokio::test]
+async fn keepalive() {
+	let mut craftflow = CraftFlow::new();
+	KeepAlive::new()
+		.interval(Duration::from_millis(50))
+		.timeout(Duration::from_millis(200))
+		.register(&mut craftflow);
+	craftflow_test_support::register(&mut craftflow);
+	let craftflow = Arc::new(craftflow);
+
+	let (mut client, task) = connect(&craftflow);
+	login(&mut client).await;
+	read_packet::<s2c::Login>(&mut client).await;
+	write_packet(&mut client, &LoginAcknowledgedV764).await;
+
+	// answer the first keep-alive
+	let keep_alive_id = read_keep_alive(&mut client).await;
+	let answer = c2s::configuration::keep_alive::v764::KeepAliveV764 { keep_alive_id };
+	write_packet(&mut client, &answer).await;
+
+	// which measures the latency
+	let conn = craftflow.connections().values().next().cloned().unwrap();
+	let state = conn.attachments().get::<KeepAliveState>().unwrap();
+	timeout(Duration::from_secs(5), async {
+		while state.latency().is_none() {
+			sleep(Duration::from_millis(10)).await;
+		}
+	})
+	.await
+	.unwrap();
+
+	// but not the next one, so the client is kicked
+	assert_ne!(read_keep_alive(&mut client).await, keep_alive_id);
+	let s2c::Configuration::Disconnect(s2c::configuration::Disconnect::V765(disconnect)) =
+		read_packet(&mut client).await
+	else {
+		panic!("expected disconnect");
+	};
+	assert_eq!(disconnect.reason, NbtValue::try_from("Timed out").unwrap());
+
+	assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+	task.await.unwrap().unwrap();
+	assert!(craftflow.connections().is_empty());
+}
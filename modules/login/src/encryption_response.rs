@@ -1,4 +1,4 @@
-use crate::{Login, VERIFY_TOKEN};
+use crate::{Login, LoginInfo, VERIFY_TOKEN};
 use craftflow::{ConnId, CraftFlow, packet_events::Packet};
 use craftflow_protocol::{
 	c2s::login::{EncryptionBegin, encryption_begin::v759::Crypto},
@@ -59,7 +59,7 @@ pub async fn encryption_response(
 				if let Some(token) = decrypted_verification_token {
 					if token != VERIFY_TOKEN.as_bytes() {
 						error!("{} sent bad encryption response", cf.get(conn_id));
-						cf.disconnect(conn_id).await;
+						cf.kick(conn_id, "Invalid encryption response").await;
 
 						return ControlFlow::Break(());
 					}
@@ -67,7 +67,7 @@ pub async fn encryption_response(
 
 				if decrypted_shared_secret.len() != 16 {
 					error!("{} sent bad encryption response", cf.get(conn_id));
-					cf.disconnect(conn_id).await;
+					cf.kick(conn_id, "Invalid encryption response").await;
 
 					return ControlFlow::Break(());
 				}
@@ -78,23 +78,16 @@ pub async fn encryption_response(
 				cf.get(conn_id).set_encryption(shared_secret);
 
 				// get the player name and uuid that the client sent in the login start packet
-				let info = cf
-					.modules
-					.get::<Login>()
-					.player_names_uuids
-					.read()
-					.unwrap()
-					.get(&conn_id)
-					.cloned();
-				let (username, uuid) = match info {
-					Some(x) => x,
+				let info = cf.get(conn_id).attachments().get::<LoginInfo>();
+				let LoginInfo { username, uuid } = match info {
+					Some(info) => LoginInfo::clone(&info),
 					None => {
 						// Honestly I dont think this is possible, but just in case
 						error!(
 							"{} sent encryption response without sending login start",
 							cf.get(conn_id)
 						);
-						cf.disconnect(conn_id).await;
+						cf.kick(conn_id, "Invalid encryption response").await;
 						return ControlFlow::Break(());
 					}
 				};
@@ -132,7 +125,7 @@ pub async fn encryption_response(
 			_ => {
 				// couldnt decrypt the shared secret or verify token
 				error!("{} sent bad encryption response :(", cf.get(conn_id));
-				cf.disconnect(conn_id).await;
+				cf.kick(conn_id, "Invalid encryption response").await;
 			}
 		}
 	}
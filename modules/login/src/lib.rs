@@ -10,14 +10,9 @@ mod encryption_response;
 mod login_start;
 mod set_compression;
 
-use craftflow::{ConnId, CraftFlow, various_events::Disconnect};
+use craftflow::CraftFlow;
 use craftflow_protocol::craftflow_nbt::{NbtValue, nbt};
 use rsa::RsaPrivateKey;
-use std::{
-	collections::BTreeMap,
-	ops::ControlFlow,
-	sync::{Arc, RwLock},
-};
 
 craftflow::init!();
 
@@ -28,11 +23,18 @@ craftflow::init!();
 pub struct Login {
 	pub rsa_key: Option<RsaPrivateKey>,
 	pub compression_threshold: Option<usize>,
-	// The usernames and UUIDs that the client sends in the LoginStart packet
-	pub player_names_uuids: RwLock<BTreeMap<ConnId, (String, Option<u128>)>>,
 	registry_data: NbtValue,
 }
 
+/// The username and UUID that the client sent in the LoginStart packet.
+///
+/// Attached to the connection, see [`ConnectionInterface::attachments`][craftflow::connection::ConnectionInterface::attachments].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginInfo {
+	pub username: String,
+	pub uuid: Option<u128>,
+}
+
 const VERIFY_TOKEN: &str = "craftflow easter egg! 🐇🐰 :D";
 
 impl Login {
@@ -43,7 +45,6 @@ impl Login {
 		Self {
 			rsa_key: None,
 			compression_threshold: None,
-			player_names_uuids: RwLock::new(BTreeMap::new()),
 			registry_data: include!("default_registry.rs"),
 		}
 	}
@@ -82,18 +83,6 @@ impl Login {
 	}
 }
 
-#[craftflow::callback(event: Disconnect)]
-async fn cleanup_player_names_uuids(cf: &Arc<CraftFlow>, conn_id: &mut ConnId) -> ControlFlow<()> {
-	cf.modules
-		.get::<Login>()
-		.player_names_uuids
-		.write()
-		.unwrap()
-		.remove(conn_id);
-
-	ControlFlow::Continue(())
-}
-
 impl Default for Login {
 	fn default() -> Self {
 		Self::new().enable_compression(256).enable_encryption(2048)
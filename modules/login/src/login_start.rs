@@ -1,4 +1,4 @@
-use crate::{Login, VERIFY_TOKEN};
+use crate::{Login, LoginInfo, VERIFY_TOKEN};
 use craftflow::{ConnId, CraftFlow, packet_events::Packet};
 use craftflow_protocol::{
 	c2s::login::LoginStart,
@@ -44,12 +44,10 @@ pub async fn login_start(
 		disabled_versions!(c2s::login::LoginStart) => unreachable!(),
 	}
 
-	cf.modules
-		.get::<Login>()
-		.player_names_uuids
-		.write()
-		.unwrap()
-		.insert(conn_id, (username.clone(), uuid));
+	cf.get(conn_id).attachments().insert(LoginInfo {
+		username: username.clone(),
+		uuid,
+	});
 
 	if let &Some(threshold) = &cf.modules.get::<Login>().compression_threshold {
 		// Send the packet to enable compression
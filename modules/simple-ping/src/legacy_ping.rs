@@ -1,4 +1,4 @@
-use crate::SimplePing;
+use crate::{SimplePing, max_players};
 use craftflow::{
 	CraftFlow,
 	connection::legacy::{LegacyPing, LegacyPingResponse},
@@ -13,7 +13,7 @@ pub async fn legacy_ping(
 ) -> ControlFlow<Option<LegacyPingResponse>> {
 	let protocol_version = 127; // pretty arbitrary, but its not gonna be compatible with any client anyway
 	let online_players = cf.connections().len() as i32; // more or less. (less)
-	let max_players = 1000; // todo after implementing max connections
+	let max_players = max_players(cf);
 	let description = &cf.modules.get::<SimplePing>().server_description;
 
 	ControlFlow::Break(Some(
@@ -19,6 +19,7 @@ craftflow::init!();
 pub struct SimplePing {
 	server_description: Text<'static>,
 	favicon: Option<Vec<u8>>,
+	max_players: usize,
 }
 
 impl SimplePing {
@@ -29,6 +30,7 @@ impl SimplePing {
 				+ text!(" A CraftFlow Server ", bold, color = "gold")
 				+ text!(">", obfuscated, font = "minecraft:alt", color = "white"),
 			favicon: Some(include_bytes!("../../../assets/icon64.png").into()),
+			max_players: usize::MAX,
 		}
 	}
 	/// Sets the description for the server.
@@ -42,6 +44,14 @@ impl SimplePing {
 		self.favicon = favicon;
 		self
 	}
+	/// Sets the max player count shown in the server list. By default there is no limit.
+	///
+	/// This only changes what is shown, the number of players has to be limited separately,
+	/// for example with the `connection-limits` module.
+	pub fn set_max_players(mut self, max_players: usize) -> Self {
+		self.max_players = max_players;
+		self
+	}
 	/// Adds the module to a CraftFlow instance.
 	pub fn register(self, craftflow: &mut CraftFlow) {
 		craftflow.modules.register(self);
@@ -55,3 +65,9 @@ impl Default for SimplePing {
 		Self::new()
 	}
 }
+
+/// The max player count shown in the server list
+pub(crate) fn max_players(cf: &CraftFlow) -> i32 {
+	let max_players = cf.modules.get::<SimplePing>().max_players;
+	max_players.min(i32::MAX as usize) as i32
+}
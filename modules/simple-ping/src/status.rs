@@ -1,4 +1,4 @@
-use crate::SimplePing;
+use crate::{SimplePing, max_players};
 use craftflow::{ConnId, CraftFlow, packet_events::Packet};
 use craftflow_protocol::{
 	SUPPORTED_VERSIONS,
@@ -82,7 +82,7 @@ pub async fn status(
 	};
 
 	let online_players = cf.connections().len() as i32; // more or less (more)
-	let max_players = 2_000_000_000; // todo after implementing max connections
+	let max_players = max_players(cf);
 	let description = cf.modules.get::<SimplePing>().server_description.clone();
 	let favicon = cf.modules.get::<SimplePing>().favicon.clone();
 
@@ -81,6 +81,13 @@ impl World {
 
 		new_id
 	}
+	/// Returns the world that the player is in, if any.
+	///
+	/// Useful as a filter for [`CraftFlow::broadcast`]:
+	/// `|conn| world.player_world(conn.id()) == Some(world_id)`
+	pub fn player_world(&self, id: ConnId) -> Option<WorldId> {
+		self.players.read().unwrap().get(&id).copied()
+	}
 	pub async fn set_player(&self, id: ConnId, world_id: WorldId) {
 		self.players
 			.write()